@@ -0,0 +1,38 @@
+extern crate utf;
+
+use std::hint::black_box;
+use std::time::Instant;
+
+use utf::UtfExt;
+
+const N: usize = 1 << 12;
+
+fn bench<F: FnMut(&[char], &mut [u8]) -> usize>(name: &str, cs: &[char], mut f: F) {
+    let mut buf = vec![0u8; 4 * cs.len()];
+    let t = Instant::now();
+    let mut n = 0;
+    for _ in 0..N { n += f(black_box(cs), &mut buf); }
+    let dt = t.elapsed();
+    black_box(n);
+    println!("{:>24}: {:>8.2} ns/char", name, dt.as_nanos() as f64 / (N * cs.len()) as f64);
+}
+
+fn main() {
+    let cs: Vec<char> =
+        "Hello, wörld! Привет, мир! 你好，世界！ 😀🙃".chars().cycle().take(1 << 10).collect();
+    bench("try_encode_utf8", &cs, |cs, bs| {
+        let mut i = 0;
+        for &c in cs { i += c.try_encode_utf8(&mut bs[i..]).unwrap().len(); }
+        i
+    });
+    bench("encode_utf8_unchecked", &cs, |cs, bs| {
+        let mut i = 0;
+        for &c in cs { i += unsafe { c.encode_utf8_unchecked(bs.get_unchecked_mut(i..)) }.len(); }
+        i
+    });
+    bench("char::encode_utf8", &cs, |cs, bs| {
+        let mut i = 0;
+        for &c in cs { i += c.encode_utf8(&mut bs[i..]).len(); }
+        i
+    });
+}
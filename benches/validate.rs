@@ -0,0 +1,41 @@
+extern crate utf;
+
+use std::hint::black_box;
+use std::time::Instant;
+
+const N: usize = 1 << 4;
+
+fn bench<F: FnMut(&[u8]) -> usize>(name: &str, bs: &[u8], mut f: F) {
+    let t = Instant::now();
+    let mut n = 0;
+    for _ in 0..N { n += f(black_box(bs)); }
+    let dt = t.elapsed();
+    black_box(n);
+    println!("{:>24}: {:>8.2} ns/byte", name, dt.as_nanos() as f64 / (N * bs.len()) as f64);
+}
+
+fn main() {
+    let bs: Vec<u8> =
+        "Hello, wörld! Привет, мир! 你好，世界！ 😀🙃".repeat(1 << 16).into_bytes();
+    println!("{} bytes", bs.len());
+
+    // `validate` and `decode_utf8` run on the automaton; `decode_slice` and `decode_wtf8`
+    // take the byte-at-a-time path, which agrees with them on valid UTF-8.
+    bench("validate (DFA)", &bs, |bs| utf::validate(bs) as usize);
+    bench("decode_slice loop", &bs, |mut bs| {
+        let mut n = 0;
+        while let Some((_, l)) = utf::decode_slice(bs) {
+            bs = &bs[l.get()..];
+            n += 1;
+        }
+        n
+    });
+    bench("str::from_utf8", &bs, |bs| std::str::from_utf8(bs).is_ok() as usize);
+    bench("decode_utf8 (DFA)", &bs, |bs| {
+        utf::decode_utf8(bs.iter().cloned()).filter(Result::is_ok).count()
+    });
+    bench("decode_wtf8", &bs, |bs| {
+        utf::wtf8::decode_wtf8(bs.iter().cloned()).filter(Result::is_ok).count()
+    });
+    bench("str::chars", &bs, |bs| unsafe { std::str::from_utf8_unchecked(bs) }.chars().count());
+}
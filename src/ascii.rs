@@ -0,0 +1,61 @@
+//! Scanning runs of ASCII many bytes at a time
+
+/// Length of the run of ASCII with which `bs` begins
+#[inline]
+pub fn ascii_len(bs: &[u8]) -> usize {
+    let mut i = chunks_len(bs);
+    while i < bs.len() && bs[i] < 0x80 { i += 1; }
+    i
+}
+
+/// Length of a run of ASCII with which `bs` begins, scanning 16 bytes at a time, which
+/// stops short of the end of the run at most at the last chunk
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+#[inline]
+fn chunks_len(bs: &[u8]) -> usize {
+    use core::arch::x86_64::*;
+
+    let mut i = 0;
+    while i + 16 <= bs.len() {
+        // SSE2 is part of x86_64, so no detection is needed.
+        let m = unsafe { _mm_movemask_epi8(_mm_loadu_si128(bs.as_ptr().add(i) as *const __m128i)) };
+        if 0 != m { return i + m.trailing_zeros() as usize }
+        i += 16;
+    }
+    i
+}
+
+/// Length of a run of ASCII with which `bs` begins, scanning 16 bytes at a time, which
+/// stops short of the end of the run at most at the last chunk
+#[cfg(all(feature = "simd", target_arch = "aarch64"))]
+#[inline]
+fn chunks_len(bs: &[u8]) -> usize {
+    use core::arch::aarch64::*;
+
+    let mut i = 0;
+    while i + 16 <= bs.len() {
+        // NEON is part of aarch64, so no detection is needed.
+        if unsafe { vmaxvq_u8(vld1q_u8(bs.as_ptr().add(i))) } >= 0x80 { return i }
+        i += 16;
+    }
+    i
+}
+
+/// Length of a run of ASCII with which `bs` begins, scanning a word at a time, which
+/// stops short of the end of the run at most at the last word
+#[cfg(not(all(feature = "simd", any(target_arch = "x86_64", target_arch = "aarch64"))))]
+#[inline]
+fn chunks_len(bs: &[u8]) -> usize {
+    use core::{mem, ptr};
+
+    const W: usize = mem::size_of::<usize>();
+    const HIGH: usize = usize::MAX / 0xFF * 0x80;
+
+    let mut i = 0;
+    while i + W <= bs.len() {
+        let w = unsafe { ptr::read_unaligned(bs.as_ptr().add(i) as *const usize) };
+        if 0 != w & HIGH { return i }
+        i += W;
+    }
+    i
+}
@@ -0,0 +1,171 @@
+//! CESU-8, which encodes each supplementary character as the UTF-8 forms of the two
+//! halves of its UTF-16 surrogate pair
+
+use core::{char, iter};
+
+use {combine_surrogates, decode_modified_nul, decode_u32, split_surrogates, Bytes};
+use {DecodeError, ErrorKind, InvalidSequence, UtfExt};
+
+/// An iterator over an iterator of bytes of the characters the bytes represent as CESU-8
+#[derive(Clone, Debug)]
+pub struct DecodeCesu8<I: Iterator<Item = u8>> {
+    iter: iter::Peekable<I>,
+    next: Option<Result<u32, DecodeError>>,
+    pub(crate) mutf8: bool,
+}
+
+/// Decodes an `Iterator` of bytes as CESU-8. The 4-byte forms of UTF-8 are invalid, as
+/// are surrogates not in a high-low pair.
+#[inline]
+pub fn decode_cesu8<I: IntoIterator<Item = u8>>(i: I) -> DecodeCesu8<I::IntoIter> {
+    DecodeCesu8 { iter: i.into_iter().peekable(), next: None, mutf8: false }
+}
+
+impl<I: Iterator<Item = u8>> DecodeCesu8<I> {
+    #[inline]
+    fn next_u32(&mut self) -> Option<Result<u32, DecodeError>> {
+        if let Some(r) = self.next.take() { return Some(r) }
+        if self.mutf8 {
+            // Modified UTF-8 encodes U+0000 in the overlong 2-byte form.
+            if let Some(r) = decode_modified_nul(&mut self.iter) { return Some(r) }
+        }
+        // CESU-8 encodes supplementary characters only as surrogate pairs, so the lead
+        // bytes of the 4-byte forms of UTF-8 can't begin any sequence.
+        if let Some(&(0xF0..=0xF4)) = self.iter.peek() {
+            self.iter.next();
+            return Some(Err(DecodeError::invalid(ErrorKind::InvalidLeadByte, 1)))
+        }
+        decode_u32(&mut self.iter, true)
+    }
+}
+
+/// Bytes which the decoder takes from `iter`, of which it notes the last
+struct Noted<'a, I: Iterator<Item = u8>> { iter: &'a mut iter::Peekable<I>, last: u8 }
+
+impl<'a, I: Iterator<Item = u8>> Bytes for Noted<'a, I> {
+    #[inline]
+    fn next_byte(&mut self) -> Option<u8> {
+        let b = self.iter.next()?;
+        self.last = b;
+        Some(b)
+    }
+    #[inline]
+    fn peek_byte(&mut self) -> Option<u8> { self.iter.peek().cloned() }
+}
+
+impl<I: Iterator<Item = u8>> Iterator for DecodeCesu8<I> {
+    type Item = Result<char, InvalidSequence>;
+    fn next(&mut self) -> Option<Result<char, InvalidSequence>> {
+        let x = match self.next_u32()? {
+            Ok(x @ 0xD800..=0xDBFF) => {
+                // Only `ED B0..=BF 80..=BF` is a low surrogate, so only a prefix of that
+                // which the input ends in may yet complete the pair: the last byte of it
+                // taken is `ED` itself, or the second.
+                let next = if Some(&0xED) == self.iter.peek() {
+                    let mut bs = Noted { iter: &mut self.iter, last: 0 };
+                    match decode_u32(&mut bs, true) {
+                        Some(Err(e)) if e.is_incomplete() && bs.last >= 0xB0 =>
+                            return Some(Err(DecodeError::incomplete(6, 3 + e.len()))),
+                        next => next,
+                    }
+                } else {
+                    self.next_u32()
+                };
+                match next {
+                    Some(Ok(y @ 0xDC00..=0xDFFF)) => combine_surrogates(x, y),
+                    None => return Some(Err(DecodeError::incomplete(6, 3))),
+                    next => {
+                        self.next = next;
+                        return Some(Err(DecodeError::invalid(ErrorKind::Surrogate, 3)))
+                    },
+                }
+            },
+            Ok(0xDC00..=0xDFFF) => return Some(Err(DecodeError::invalid(ErrorKind::Surrogate, 3))),
+            Ok(x) => x,
+            Err(e) => return Some(Err(e)),
+        };
+        Some(Ok(unsafe { char::from_u32_unchecked(x) }))
+    }
+}
+
+impl<I: iter::FusedIterator<Item = u8>> iter::FusedIterator for DecodeCesu8<I> {}
+
+/// An iterator over an iterator of characters of the bytes which represent them as CESU-8
+#[derive(Clone, Debug)]
+pub struct EncodeCesu8<I: Iterator<Item = char>> {
+    iter: I,
+    buf: [u8; 6],
+    pos: u8,
+    end: u8,
+    pub(crate) mutf8: bool,
+}
+
+/// Encodes an `Iterator` of characters as CESU-8.
+#[inline]
+pub fn encode_cesu8<I: IntoIterator<Item = char>>(i: I) -> EncodeCesu8<I::IntoIter> {
+    EncodeCesu8 { iter: i.into_iter(), buf: [0; 6], pos: 0, end: 0, mutf8: false }
+}
+
+impl<I: Iterator<Item = char>> Iterator for EncodeCesu8<I> {
+    type Item = u8;
+    fn next(&mut self) -> Option<u8> {
+        if self.pos == self.end {
+            let c = self.iter.next()?;
+            self.pos = 0;
+            self.end = if c.len_utf16() > 1 {
+                let (hi, lo) = split_surrogates(c as u32);
+                (hi as u32).try_encode_utf8(&mut self.buf[..3]);
+                (lo as u32).try_encode_utf8(&mut self.buf[3..]);
+                6
+            } else if self.mutf8 && '\0' == c {
+                self.buf[..2].copy_from_slice(&[0xC0, 0x80]);
+                2
+            } else {
+                c.try_encode_utf8(&mut self.buf).map_or(0, |s| s.len()) as u8
+            };
+        }
+        self.pos += 1;
+        Some(self.buf[self.pos as usize - 1])
+    }
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let l = (self.end - self.pos) as usize;
+        let (lo, hi) = self.iter.size_hint();
+        (lo.saturating_add(l), hi.and_then(|hi| hi.checked_mul(6)).and_then(|hi| hi.checked_add(l)))
+    }
+}
+
+impl<I: iter::FusedIterator<Item = char>> iter::FusedIterator for EncodeCesu8<I> {}
+
+#[test]
+fn test_cesu8() {
+    use std::vec::Vec;
+    use std::iter::FromIterator;
+    use {incomplete, invalid, ErrorKind::*};
+
+    for &(s, bs) in [("", &[] as &[u8]),
+                     ("A♥", b"A\xE2\x99\xA5"),
+                     ("😀A", b"\xED\xA0\xBD\xED\xB8\x80A"),
+                     ("\u{10FFFF}", b"\xED\xAF\xBF\xED\xBF\xBF")].iter() {
+        assert_eq!(bs, &Vec::from_iter(encode_cesu8(s.chars()))[..], "str = {:?}", s);
+        assert_eq!(Vec::from_iter(s.chars().map(Ok)), Vec::from_iter(decode_cesu8(bs.iter().cloned())),
+                   "str = {:?}", s);
+    }
+    for &(bs, rs) in [(b"\xF0\x9F\x98\x80A" as &[u8],
+                       &[invalid(InvalidLeadByte, 1), invalid(UnexpectedContinuation, 1),
+                         invalid(UnexpectedContinuation, 1), invalid(UnexpectedContinuation, 1),
+                         Ok('A')] as &[_]),
+                      (b"\xED\xA0\xBD\xF4A", &[invalid(Surrogate, 3), invalid(InvalidLeadByte, 1), Ok('A')]),
+                      (b"\xED\xA0\xBDA", &[invalid(Surrogate, 3), Ok('A')]),
+                      (b"\xED\xA0\xBD\xED\xA0\xBD\xED\xB8\x80", &[invalid(Surrogate, 3), Ok('😀')]),
+                      (b"\xED\xB8\x80A", &[invalid(Surrogate, 3), Ok('A')]),
+                      (b"\xED\xA0\xBD", &[incomplete(6, 3)]),
+                      (b"\xED\xA0\xBD\xED", &[incomplete(6, 4)]),
+                      (b"\xED\xA0\xBD\xC3", &[invalid(Surrogate, 3), incomplete(2, 1)]),
+                      (b"\xED\xA0\xBD\xE2\x99", &[invalid(Surrogate, 3), incomplete(3, 2)]),
+                      (b"\xED\xA0\xBD\xED\xA0", &[invalid(Surrogate, 3), incomplete(3, 2)]),
+                      (b"\xED\xA0\xBD\xED\x9F\xBF", &[invalid(Surrogate, 3), Ok('\u{D7FF}')]),
+                      (b"\xED\xA0\xBD\xED\xB8", &[incomplete(6, 5)])].iter() {
+        assert_eq!(rs, &Vec::from_iter(decode_cesu8(bs.iter().cloned()))[..], "bytes = {:02X?}", bs);
+    }
+}
@@ -0,0 +1,42 @@
+//! The deterministic finite automaton of Björn Höhrmann's UTF-8 decoder, which decodes
+//! with a table lookup and a shift per byte
+
+/// The state between characters, in which the automaton accepts
+pub const ACCEPT: u8 = 0;
+/// The state in which the automaton has rejected its input
+pub const REJECT: u8 = 12;
+
+/// Class of each byte, which is also the number of high bits to clear of a lead byte
+static CLASSES: [u8; 256] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9,
+    7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7,
+    8, 8, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2,
+    10, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 4, 3, 3, 11, 6, 6, 6, 5, 8, 8, 8, 8, 8, 8, 8, 8, 8, 8, 8,
+];
+
+/// Transitions, indexed by state plus class
+static TRANSITIONS: [u8; 108] = [
+     0, 12, 24, 36, 60, 96, 84, 12, 12, 12, 48, 72, 12, 12, 12, 12, 12, 12, 12, 12, 12, 12, 12, 12,
+    12,  0, 12, 12, 12, 12, 12,  0, 12,  0, 12, 12, 12, 24, 12, 12, 12, 12, 12, 24, 12, 24, 12, 12,
+    12, 12, 12, 12, 12, 12, 12, 24, 12, 12, 12, 12, 12, 24, 12, 12, 12, 12, 12, 12, 12, 24, 12, 12,
+    12, 12, 12, 12, 12, 12, 12, 36, 12, 36, 12, 12, 12, 36, 12, 12, 12, 12, 12, 36, 12, 36, 12, 12,
+    12, 36, 12, 12, 12, 12, 12, 12, 12, 12, 12, 12,
+];
+
+/// Take the automaton from `state` on `b`, and accumulate the code point in `x`.
+#[inline]
+pub fn step(state: u8, x: &mut u32, b: u8) -> u8 {
+    let class = CLASSES[b as usize];
+    *x = if ACCEPT == state { (0xFF >> class) & b as u32 } else { (*x << 6) | b as u32 & 0x3F };
+    TRANSITIONS[(state + class) as usize]
+}
+
+/// Take the automaton from `state` on `b`, not accumulating a code point.
+#[inline]
+pub fn validate_step(state: u8, b: u8) -> u8 {
+    TRANSITIONS[(state + CLASSES[b as usize]) as usize]
+}
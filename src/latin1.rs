@@ -0,0 +1,82 @@
+//! Latin-1, or ISO-8859-1, whose bytes are each the code point of the same value, U+0000
+//! to U+00FF
+
+use core::iter;
+
+use {decode_utf8, DecodeUtf8, InvalidSequence};
+
+/// An iterator over an iterator of bytes of Latin-1 of the bytes which represent the same
+/// characters as UTF-8
+#[derive(Clone, Debug)]
+pub struct EncodeLatin1ToUtf8<I: Iterator<Item = u8>> { iter: I, lo: Option<u8> }
+
+/// Transcodes an `Iterator` of bytes from Latin-1 to UTF-8, in which each byte takes 1 or
+/// 2 bytes.
+#[inline]
+pub fn encode_latin1_to_utf8<I: IntoIterator<Item = u8>>(i: I) -> EncodeLatin1ToUtf8<I::IntoIter> {
+    EncodeLatin1ToUtf8 { iter: i.into_iter(), lo: None }
+}
+
+impl<I: Iterator<Item = u8>> Iterator for EncodeLatin1ToUtf8<I> {
+    type Item = u8;
+    #[inline]
+    fn next(&mut self) -> Option<u8> {
+        if let Some(lo) = self.lo.take() { return Some(lo) }
+        let b = self.iter.next()?;
+        if b < 0x80 { return Some(b) }
+        self.lo = Some(b & 0x3F | 0x80);
+        Some(b >> 6 | 0xC0)
+    }
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let l = self.lo.is_some() as usize;
+        let (lo, hi) = self.iter.size_hint();
+        (lo.saturating_add(l), hi.and_then(|hi| hi.checked_mul(2)).and_then(|hi| hi.checked_add(l)))
+    }
+}
+
+impl<I: iter::FusedIterator<Item = u8>> iter::FusedIterator for EncodeLatin1ToUtf8<I> {}
+
+/// An iterator over an iterator of bytes of UTF-8 of the bytes which represent the same
+/// characters as Latin-1
+#[derive(Clone, Debug)]
+pub struct DecodeUtf8ToLatin1<I: Iterator<Item = u8>>(DecodeUtf8<I>);
+
+/// Transcodes an `Iterator` of bytes from UTF-8 to Latin-1, yielding `Err(Ok(_))` of each
+/// character beyond U+00FF, and `Err(Err(_))` of each invalid sequence.
+#[inline]
+pub fn decode_utf8_to_latin1<I: IntoIterator<Item = u8>>(i: I) -> DecodeUtf8ToLatin1<I::IntoIter> {
+    DecodeUtf8ToLatin1(decode_utf8(i))
+}
+
+impl<I: Iterator<Item = u8>> Iterator for DecodeUtf8ToLatin1<I> {
+    type Item = Result<u8, Result<char, InvalidSequence>>;
+    #[inline]
+    fn next(&mut self) -> Option<Result<u8, Result<char, InvalidSequence>>> {
+        Some(match self.0.next()? {
+            Ok(c) if (c as u32) < 0x100 => Ok(c as u8),
+            r => Err(r),
+        })
+    }
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) { self.0.size_hint() }
+}
+
+impl<I: iter::FusedIterator<Item = u8>> iter::FusedIterator for DecodeUtf8ToLatin1<I> {}
+
+#[test]
+fn test_latin1() {
+    use std::vec::Vec;
+    use std::iter::FromIterator;
+    use {encode_utf8, invalid, ErrorKind};
+
+    let latin1 = Vec::from_iter(0..=0xFF);
+    let utf8 = Vec::from_iter(encode_utf8(latin1.iter().map(|&b| char::from(b))));
+    assert_eq!(utf8, Vec::from_iter(encode_latin1_to_utf8(latin1.iter().cloned())));
+    assert_eq!(Vec::from_iter(latin1.iter().cloned().map(Ok)),
+               Vec::from_iter(decode_utf8_to_latin1(utf8.iter().cloned())));
+    let bs = b"A\xE2\x99\xA5\xC3\xC3\xA9\xEF\xBF\xBD";
+    assert_eq!(&[Ok(b'A'), Err(Ok('♥')), Err(invalid(ErrorKind::TruncatedSequence, 1)), Ok(0xE9),
+                 Err(Ok('\u{FFFD}'))][..],
+               &Vec::from_iter(decode_utf8_to_latin1(bs.iter().cloned()))[..]);
+}
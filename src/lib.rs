@@ -1,157 +1,4238 @@
 #![no_std]
 
-#[cfg(test)]
+#[cfg(any(test, feature = "std"))]
 extern crate std;
+#[cfg(feature = "alloc")]
+extern crate alloc;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "embedded-io")]
+extern crate embedded_io;
+#[cfg(feature = "bytes")]
+extern crate bytes;
+#[cfg(feature = "heapless")]
+extern crate heapless;
+#[cfg(feature = "proptest")]
+extern crate proptest;
+
+use core::{char::*, fmt, iter, num::NonZeroUsize, str};
 
-use core::{char::*, iter, num::NonZeroUsize, str};
+mod ascii;
+mod dfa;
+
+pub mod cesu8;
+pub mod latin1;
+pub mod mutf8;
+#[cfg(feature = "proptest")]
+pub mod strategy;
+pub mod wtf8;
+#[cfg(feature = "alloc")]
+use alloc::{borrow::Cow, string::String, vec::Vec};
 
 /// An iterator over an iterator of bytes of the characters the bytes represent
 /// as UTF-8
 #[derive(Clone, Debug)]
-pub struct DecodeUtf8<I: Iterator<Item = u8>>(iter::Peekable<I>);
+pub struct DecodeUtf8<I: Iterator<Item = u8>>(iter::Peekable<I>, Tail, Option<Result<char, DecodeError>>);
 
 /// Decodes an `Iterator` of bytes as UTF-8.
 #[inline]
 pub fn decode_utf8<I: IntoIterator<Item = u8>>(i: I) -> DecodeUtf8<I::IntoIter> {
-    DecodeUtf8(i.into_iter().peekable())
+    DecodeUtf8(i.into_iter().peekable(), Tail::new(&[], None), None)
+}
+
+/// Decodes a `Peekable` of bytes as UTF-8, keeping any byte it has peeked at, so that
+/// with `DecodeUtf8::into_inner` one can switch between decoding and reading bytes.
+#[inline]
+pub fn from_peekable<I: Iterator<Item = u8>>(p: iter::Peekable<I>) -> DecodeUtf8<I> {
+    DecodeUtf8(p, Tail::new(&[], None), None)
+}
+
+/// Decodes as UTF-8 at most the first `max_bytes` bytes of an `Iterator`, never reading
+/// further. A character which the limit cuts short is `Incomplete`, even if the bytes past
+/// the limit would make it invalid; a limit which falls between characters ends the
+/// output cleanly after the last of them.
+#[inline]
+pub fn decode_utf8_limited<I: IntoIterator<Item = u8>>(i: I, max_bytes: usize)
+  -> DecodeUtf8<iter::Take<I::IntoIter>> {
+    decode_utf8(i.into_iter().take(max_bytes))
+}
+
+/// An iterator over an iterator of bytes of the characters the bytes represent
+/// as UTF-8, with U+FFFD REPLACEMENT CHARACTER, or another replacement, in place of each
+/// invalid sequence
+#[derive(Clone, Debug)]
+pub struct DecodeUtf8Lossy<I: Iterator<Item = u8>>(DecodeUtf8<I>, char);
+
+/// Decodes an `Iterator` of bytes as UTF-8, replacing each maximal invalid subsequence
+/// with U+FFFD REPLACEMENT CHARACTER, as `String::from_utf8_lossy` does.
+#[inline]
+pub fn decode_utf8_lossy<I: IntoIterator<Item = u8>>(i: I) -> DecodeUtf8Lossy<I::IntoIter> {
+    decode_utf8_lossy_with(i, '\u{FFFD}')
+}
+
+/// Decodes an `Iterator` of bytes as UTF-8 as `decode_utf8_lossy` does, but with
+/// `replacement` in place of each maximal invalid subsequence. Each invalid subsequence
+/// is still one `char` of output, whatever the length of `replacement` in UTF-8.
+#[inline]
+pub fn decode_utf8_lossy_with<I: IntoIterator<Item = u8>>(i: I, replacement: char)
+  -> DecodeUtf8Lossy<I::IntoIter> {
+    DecodeUtf8Lossy(decode_utf8(i), replacement)
+}
+
+/// An iterator over an iterator of bytes of the characters the bytes represent
+/// as UTF-8, skipping each invalid sequence
+#[derive(Clone, Debug)]
+pub struct DecodeUtf8Filter<I: Iterator<Item = u8>>(DecodeUtf8<I>);
+
+/// Decodes an `Iterator` of bytes as UTF-8, dropping each maximal invalid subsequence.
+#[inline]
+pub fn decode_utf8_filter<I: IntoIterator<Item = u8>>(i: I) -> DecodeUtf8Filter<I::IntoIter> {
+    DecodeUtf8Filter(decode_utf8(i))
+}
+
+/// An iterator over an iterator of bytes of the characters the bytes represent
+/// as UTF-8, with what a function gives, if anything, in place of each invalid sequence
+#[derive(Clone)]
+pub struct DecodeUtf8With<I: Iterator<Item = u8>, F> { iter: DecodeUtf8<I>, on_error: F }
+
+/// Decodes an `Iterator` of bytes as UTF-8, calling `on_error` with each maximal invalid
+/// subsequence, to yield the character it returns in its place, or nothing if `None`.
+#[inline]
+pub fn decode_utf8_with<I, F>(i: I, on_error: F) -> DecodeUtf8With<I::IntoIter, F>
+  where I: IntoIterator<Item = u8>, F: FnMut(InvalidSequence) -> Option<char> {
+    DecodeUtf8With { iter: decode_utf8(i), on_error }
+}
+
+impl<I: Iterator<Item = u8> + fmt::Debug, F> fmt::Debug for DecodeUtf8With<I, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DecodeUtf8With").field("iter", &self.iter).finish()
+    }
+}
+
+impl<I: Iterator<Item = u8>, F: FnMut(InvalidSequence) -> Option<char>> Iterator for DecodeUtf8With<I, F> {
+    type Item = char;
+    #[inline]
+    fn next(&mut self) -> Option<char> {
+        let on_error = &mut self.on_error;
+        self.iter.by_ref().filter_map(|r| r.or_else(|e| on_error(e).ok_or(())).ok()).next()
+    }
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) { (0, self.iter.size_hint().1) }
+}
+
+impl<I, F> iter::FusedIterator for DecodeUtf8With<I, F>
+  where I: iter::FusedIterator<Item = u8>, F: FnMut(InvalidSequence) -> Option<char> {}
+
+impl<I, F> DoubleEndedIterator for DecodeUtf8With<I, F>
+  where I: DoubleEndedIterator<Item = u8>, F: FnMut(InvalidSequence) -> Option<char> {
+    #[inline]
+    fn next_back(&mut self) -> Option<char> {
+        let on_error = &mut self.on_error;
+        self.iter.by_ref().rev().filter_map(|r| r.or_else(|e| on_error(e).ok_or(())).ok()).next()
+    }
+}
+
+/// An iterator over an iterator of bytes of the characters the bytes represent
+/// as UTF-8 but for a leading byte order mark
+#[derive(Clone, Debug)]
+pub struct DecodeUtf8SkipBom<I: Iterator<Item = u8>> { iter: DecodeUtf8<I>, started: bool }
+
+/// Decodes an `Iterator` of bytes as UTF-8, dropping U+FEFF ZERO WIDTH NO-BREAK SPACE, the
+/// byte order mark, if it's the first character, but no other.
+#[inline]
+pub fn decode_utf8_skip_bom<I: IntoIterator<Item = u8>>(i: I) -> DecodeUtf8SkipBom<I::IntoIter> {
+    DecodeUtf8SkipBom { iter: decode_utf8(i), started: false }
+}
+
+impl<I: Iterator<Item = u8>> Iterator for DecodeUtf8SkipBom<I> {
+    type Item = Result<char, DecodeError>;
+    #[inline]
+    fn next(&mut self) -> Option<Result<char, DecodeError>> {
+        if !self.started {
+            self.started = true;
+            match self.iter.next()? { Ok('\u{FEFF}') => (), r => return Some(r) }
+        }
+        self.iter.next()
+    }
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (lo, hi) = self.iter.size_hint();
+        (if self.started { lo } else { lo.saturating_sub(1) }, hi)
+    }
+}
+
+impl<I: iter::FusedIterator<Item = u8>> iter::FusedIterator for DecodeUtf8SkipBom<I> {}
+
+/// An iterator over an iterator of bytes of the characters the bytes represent
+/// as UTF-8, noncharacters being invalid
+#[derive(Clone, Debug)]
+pub struct DecodeUtf8Strict<I: Iterator<Item = u8>>(DecodeUtf8<I>);
+
+/// Decodes an `Iterator` of bytes as UTF-8, taking each noncharacter for an invalid
+/// sequence of kind `Noncharacter`, for protocols which forbid them.
+#[inline]
+pub fn decode_utf8_strict<I: IntoIterator<Item = u8>>(i: I) -> DecodeUtf8Strict<I::IntoIter> {
+    DecodeUtf8Strict(decode_utf8(i))
+}
+
+/// Whether `c` is a noncharacter: one of U+FDD0 to U+FDEF, or the last 2 code points of
+/// a plane, such as U+FFFE and U+FFFF
+#[inline]
+pub const fn is_noncharacter(c: char) -> bool {
+    let x = c as u32;
+    x & 0xFFFE == 0xFFFE || 0xFDD0 <= x && x <= 0xFDEF
+}
+
+#[inline]
+fn strict(r: Result<char, DecodeError>) -> Result<char, DecodeError> {
+    match r {
+        Ok(c) if is_noncharacter(c) => Err(DecodeError::invalid(ErrorKind::Noncharacter, c.len_utf8())),
+        r => r,
+    }
+}
+
+/// An iterator over an iterator of bytes of the characters the bytes represent
+/// as UTF-8, C0 and C1 controls being invalid
+#[derive(Clone, Debug)]
+pub struct DecodeUtf8NoControls<I: Iterator<Item = u8>> { iter: DecodeUtf8<I>, whitespace: bool }
+
+/// Decodes an `Iterator` of bytes as UTF-8, taking each C0 or C1 control for an invalid
+/// sequence of kind `Control`, for protocols which forbid them.
+#[inline]
+pub fn decode_utf8_no_controls<I: IntoIterator<Item = u8>>(i: I) -> DecodeUtf8NoControls<I::IntoIter> {
+    DecodeUtf8NoControls { iter: decode_utf8(i), whitespace: false }
+}
+
+/// Decodes an `Iterator` of bytes as UTF-8, as `decode_utf8_no_controls` does, but for
+/// tab, line feed, and carriage return, which it admits.
+#[inline]
+pub fn decode_utf8_no_controls_but_whitespace<I: IntoIterator<Item = u8>>(i: I)
+  -> DecodeUtf8NoControls<I::IntoIter> {
+    DecodeUtf8NoControls { iter: decode_utf8(i), whitespace: true }
+}
+
+/// Whether `c` is a C0 control, U+0000 to U+001F, or a C1 control, U+0080 to U+009F
+#[inline]
+pub const fn is_control_forbidden(c: char) -> bool { matches!(c, '\0'..='\x1F' | '\u{80}'..='\u{9F}') }
+
+impl<I: Iterator<Item = u8>> DecodeUtf8NoControls<I> {
+    #[inline]
+    fn check(&self, r: Result<char, DecodeError>) -> Result<char, DecodeError> {
+        match r {
+            Ok(c) if is_control_forbidden(c) && !(self.whitespace && matches!(c, '\t' | '\n' | '\r')) =>
+                Err(DecodeError::invalid(ErrorKind::Control, c.len_utf8())),
+            r => r,
+        }
+    }
+}
+
+/// An iterator over an iterator of bytes of the code points the bytes represent as
+/// UTF-8, surrogates included
+#[derive(Clone, Debug)]
+pub struct DecodeUtf8U32<I: Iterator<Item = u8>>(iter::Peekable<I>);
+
+/// Decodes an `Iterator` of bytes as UTF-8, but admitting the 3-byte forms of surrogates,
+/// as `u32::try_encode_utf8` writes them, so a code point may not be a Unicode scalar
+/// value, and so may not be a `char`.
+#[inline]
+pub fn decode_utf8_u32<I: IntoIterator<Item = u8>>(i: I) -> DecodeUtf8U32<I::IntoIter> {
+    DecodeUtf8U32(i.into_iter().peekable())
 }
 
 /// `<DecodeUtf8 as Iterator>::next` returns this for an invalid input sequence.
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
-pub struct InvalidSequence(());
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DecodeError {
+    kind: ErrorKind,
+    len: NonZeroUsize,
+    /// Length of the whole sequence if the input ended in it, else 0
+    needed: usize,
+}
 
-impl<I: Iterator<Item = u8>> Iterator for DecodeUtf8<I> {
-    type Item = Result<char, InvalidSequence>;
-    fn next(&mut self) -> Option<Result<char, InvalidSequence>> {
-        self.0.next().map(|b| {
-            if b & 0x80 == 0 { Ok(b as char) } else {
-                let l = (!b).leading_zeros() as usize; // number of bytes in UTF-8 representation
-                if l < 2 || l > 6 { return Err(InvalidSequence(())) };
-                let mut x = (b as u32) & (0x7F >> l);
-                for _ in 0..l-1 {
-                    match self.0.peek() {
-                        Some(&b) if b & 0xC0 == 0x80 => {
-                            self.0.next();
-                            x = (x << 6) | (b as u32) & 0x3F;
-                        },
-                        _ => return Err(InvalidSequence(())),
-                    }
-                }
-                match from_u32(x) {
-                    Some(x) if l == x.len_utf8() => Ok(x),
-                    _ => Err(InvalidSequence(())),
+/// Why a sequence is invalid
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ErrorKind {
+    /// A continuation byte, or trailing code unit, begins the sequence.
+    UnexpectedContinuation,
+    /// The first byte, or code unit, can't begin any sequence.
+    InvalidLeadByte,
+    /// The sequence is longer than the shortest form of its code point.
+    Overlong,
+    /// The sequence is of a surrogate code point, or an unpaired surrogate.
+    Surrogate,
+    /// The sequence is of a code point above U+10FFFF.
+    OutOfRange,
+    /// The sequence ends early, before a byte which can't continue it, or at the end of
+    /// the input.
+    TruncatedSequence,
+    /// The sequence is of a noncharacter, which `decode_utf8_strict` forbids.
+    Noncharacter,
+    /// The sequence is of a control character, which `decode_utf8_no_controls` forbids.
+    Control,
+}
+
+impl DecodeError {
+    /// Error of `kind` in a sequence of `len` code units, which must be nonzero
+    #[inline]
+    const fn invalid(kind: ErrorKind, len: usize) -> Self {
+        DecodeError { kind, len: unsafe { NonZeroUsize::new_unchecked(len) }, needed: 0 }
+    }
+
+    /// Error of the input ending after `got` code units of a sequence `needed` long
+    #[inline]
+    const fn incomplete(needed: usize, got: usize) -> Self {
+        DecodeError { needed, ..Self::invalid(ErrorKind::TruncatedSequence, got) }
+    }
+
+    /// Why the sequence is invalid
+    #[inline]
+    pub fn kind(&self) -> ErrorKind { self.kind }
+
+    /// Number of code units of the input which the invalid or incomplete sequence took,
+    /// i.e. the length of a maximal subpart in UTF-8
+    #[allow(clippy::len_without_is_empty)]
+    #[inline]
+    pub fn len(&self) -> usize { self.len.get() }
+
+    /// Whether the input ended in the sequence, so more input could yet complete it
+    #[inline]
+    pub fn is_incomplete(&self) -> bool { 0 != self.needed }
+
+    /// Number of code units of the whole sequence, if the input ended in it
+    #[inline]
+    pub fn needed(&self) -> Option<usize> { if self.is_incomplete() { Some(self.needed) } else { None } }
+}
+
+/// Why a UTF-8 sequence begun by `b0` is invalid, if the decoder rejected `b0` itself, or
+/// else the byte `b` later in the sequence
+#[inline]
+fn invalid_kind(b0: u8, b: Option<u8>) -> ErrorKind {
+    match b {
+        None => match b0 {
+            0x80..=0xBF => ErrorKind::UnexpectedContinuation,
+            0xC0 | 0xC1 => ErrorKind::Overlong,
+            0xF5..=0xF7 => ErrorKind::OutOfRange,
+            _ => ErrorKind::InvalidLeadByte,
+        },
+        Some(b) if b & 0xC0 != 0x80 => ErrorKind::TruncatedSequence,
+        // A continuation byte out of the range of `second_range`
+        Some(_) => match b0 {
+            0xE0 | 0xF0 => ErrorKind::Overlong,
+            0xED => ErrorKind::Surrogate,
+            _ => ErrorKind::OutOfRange,
+        },
+    }
+}
+
+/// Former name of `DecodeError`
+pub type InvalidSequence = DecodeError;
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.needed() {
+            None => f.write_str("invalid UTF-8 byte sequence"),
+            Some(needed) =>
+                write!(f, "incomplete UTF-8 byte sequence: {} of {} bytes", self.len(), needed),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl core::error::Error for DecodeError {}
+
+/// A source of bytes with one byte of lookahead
+trait Bytes {
+    fn next_byte(&mut self) -> Option<u8>;
+    fn peek_byte(&mut self) -> Option<u8>;
+}
+
+impl Bytes for &[u8] {
+    #[inline]
+    fn next_byte(&mut self) -> Option<u8> {
+        let (&b, bs) = self.split_first()?;
+        *self = bs;
+        Some(b)
+    }
+    #[inline]
+    fn peek_byte(&mut self) -> Option<u8> { self.first().cloned() }
+}
+
+/// Greatest number of bytes in the UTF-8 representation of a character
+pub const MAX_UTF8_LEN: usize = 4;
+
+/// Greatest number of bytes in the representation of a code point in the original UTF-8,
+/// as `decode_utf8_legacy` decodes
+pub const MAX_UTF8_LEN_LEGACY: usize = 6;
+
+/// Greatest number of code units in the UTF-16 representation of a character
+pub const MAX_UTF16_LEN: usize = 2;
+
+/// Number of bytes in the UTF-8 sequence begun by `first_byte`, or 0 if it begins none,
+/// being a continuation byte, or one of `C0`, `C1` and `F5` to `FF`
+#[inline]
+pub const fn utf8_char_width(first_byte: u8) -> usize {
+    match first_byte {
+        0x00..=0x7F => 1,
+        0xC2..=0xDF => 2,
+        0xE0..=0xEF => 3,
+        0xF0..=0xF4 => 4,
+        _ => 0,
+    }
+}
+
+/// Range of the second byte of a UTF-8 sequence begun by `b0`, which excludes overlong
+/// forms, code points above U+10FFFF, and surrogates unless `surrogates`
+#[inline]
+fn second_range(b0: u8, surrogates: bool) -> (u8, u8) {
+    match b0 {
+        0xE0 => (0xA0, 0xBF),
+        0xED if !surrogates => (0x80, 0x9F),
+        0xF0 => (0x90, 0xBF),
+        0xF4 => (0x80, 0x8F),
+        _ => (0x80, 0xBF),
+    }
+}
+
+/// What was incomplete at the end of the source, begun by `b0`, is invalid for the byte
+/// `next` which follows it, if the input goes on.
+#[inline]
+fn complete(b0: u8, r: Result<char, DecodeError>, next: Option<u8>) -> Result<char, DecodeError> {
+    match (r, next) {
+        (Err(e), Some(b)) if e.is_incomplete() =>
+            Err(DecodeError::invalid(invalid_kind(b0, Some(b)), e.len())),
+        (r, _) => r,
+    }
+}
+
+/// Decode the next character from `bs`, consuming the lead byte and as many of its
+/// continuation bytes as are valid, so that each invalid sequence is a maximal subpart
+/// in terms of the Unicode standard, § 3.9.
+#[inline]
+fn decode<B: Bytes>(bs: &mut B) -> Option<Result<char, DecodeError>> {
+    let b0 = bs.next_byte()?;
+    let mut x = 0;
+    let mut state = dfa::step(dfa::ACCEPT, &mut x, b0);
+    if dfa::REJECT == state { return Some(Err(DecodeError::invalid(invalid_kind(b0, None), 1))) }
+    let mut k = 1;
+    while dfa::ACCEPT != state {
+        let b = match bs.peek_byte() {
+            Some(b) => b,
+            None => return Some(Err(DecodeError::incomplete(utf8_char_width(b0), k))),
+        };
+        // A byte which the automaton rejects after the lead byte begins the next sequence.
+        let mut y = x;
+        state = dfa::step(state, &mut y, b);
+        if dfa::REJECT == state { return Some(Err(DecodeError::invalid(invalid_kind(b0, Some(b)), k))) }
+        bs.next_byte();
+        x = y;
+        k += 1;
+    }
+    Some(Ok(unsafe { from_u32_unchecked(x) }))
+}
+
+/// Decode the next code point from `bs` as `decode` does, but admit surrogates if
+/// `surrogates`.
+fn decode_u32<B: Bytes>(bs: &mut B, surrogates: bool) -> Option<Result<u32, DecodeError>> {
+    let b0 = bs.next_byte()?;
+    let l = utf8_char_width(b0);
+    Some(match l {
+        0 => Err(DecodeError::invalid(invalid_kind(b0, None), 1)),
+        1 => Ok(b0 as u32),
+        _ => {
+            let mut x = (b0 as u32) & (0x7F >> l);
+            for k in 1..l {
+                let (lo, hi) = if 1 == k { second_range(b0, surrogates) } else { (0x80, 0xBF) };
+                match bs.peek_byte() {
+                    Some(b) if lo <= b && b <= hi => {
+                        bs.next_byte();
+                        x = (x << 6) | (b as u32) & 0x3F;
+                    },
+                    Some(b) => return Some(Err(DecodeError::invalid(invalid_kind(b0, Some(b)), k))),
+                    None => return Some(Err(DecodeError::incomplete(l, k))),
                 }
             }
-        })
+            Ok(x)
+        },
+    })
+}
+
+impl<I: Iterator<Item = u8>> Bytes for iter::Peekable<I> {
+    #[inline]
+    fn next_byte(&mut self) -> Option<u8> { self.next() }
+    #[inline]
+    fn peek_byte(&mut self) -> Option<u8> { self.peek().cloned() }
+}
+
+/// Bytes taken off the back of the source by `next_back` but not yet decoded, in
+/// stream order, and, if anything has been decoded off the back, so that the source no
+/// longer ends where the input does, the byte which followed it, which decoding may peek
+/// at but never take
+#[derive(Clone, Copy, Debug)]
+struct Tail { bs: [u8; 3], start: u8, end: u8, next: Option<u8> }
+
+impl Tail {
+    #[inline]
+    fn new(bs: &[u8], next: Option<u8>) -> Self {
+        let mut tail = Tail { bs: [0; 3], start: 0, end: bs.len() as u8, next };
+        tail.bs[..bs.len()].copy_from_slice(bs);
+        tail
+    }
+
+    #[inline]
+    fn front(&self) -> Option<u8> {
+        if self.start < self.end { Some(self.bs[self.start as usize]) } else { None }
+    }
+
+    #[inline]
+    fn pop_front(&mut self) -> Option<u8> {
+        let b = self.front()?;
+        self.start += 1;
+        Some(b)
+    }
+
+    #[inline]
+    fn pop_back(&mut self) -> Option<u8> {
+        if self.start < self.end { self.end -= 1; Some(self.bs[self.end as usize]) } else { None }
+    }
+}
+
+impl<I: Iterator<Item = u8>> Bytes for DecodeUtf8<I> {
+    #[inline]
+    fn next_byte(&mut self) -> Option<u8> { self.0.next().or_else(|| self.1.pop_front()) }
+    #[inline]
+    fn peek_byte(&mut self) -> Option<u8> {
+        self.0.peek().cloned().or_else(|| self.1.front()).or(self.1.next)
+    }
+}
+
+impl<I: Iterator<Item = u8>> DecodeUtf8<I> {
+    /// The next item, which `next` then returns, consuming its bytes now
+    #[inline]
+    pub fn peek_char(&mut self) -> Option<&Result<char, DecodeError>> {
+        if self.2.is_none() { self.2 = self.next(); }
+        self.2.as_ref()
+    }
+
+    /// The underlying bytes not yet decoded, as a `Peekable`, so that a byte it has
+    /// peeked at but not consumed is not lost. The bytes of a character which `peek_char`
+    /// has decoded are gone, as are those which `next_back` has taken from the end but
+    /// not yet decoded.
+    #[inline]
+    pub fn into_inner(self) -> iter::Peekable<I> { self.0 }
+}
+
+impl<I: Iterator<Item = u8>> Iterator for DecodeUtf8<I> {
+    type Item = Result<char, DecodeError>;
+    #[inline]
+    fn next(&mut self) -> Option<Result<char, DecodeError>> {
+        if let Some(r) = self.2.take() { return Some(r) }
+        decode(self)
+    }
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        // Each item takes 1 to 4 bytes.
+        let l = (self.1.end - self.1.start) as usize;
+        let p = self.2.is_some() as usize;
+        let (lo, hi) = self.0.size_hint();
+        (lo.saturating_add(l).div_ceil(4).saturating_add(p),
+         hi.and_then(|hi| hi.checked_add(l)).and_then(|hi| hi.checked_add(p)))
+    }
+}
+
+impl<I: iter::FusedIterator<Item = u8>> iter::FusedIterator for DecodeUtf8<I> {}
+
+impl<I: DoubleEndedIterator<Item = u8>> DoubleEndedIterator for DecodeUtf8<I> {
+    fn next_back(&mut self) -> Option<Result<char, DecodeError>> {
+        // Take the trailing run of continuation bytes, and the byte before it, which
+        // must begin whatever sequence the forward decoder would find there.
+        let mut bs = [0u8; 4];
+        let mut k = bs.len();
+        while k > 0 {
+            match self.1.pop_back().or_else(|| self.0.next_back()) {
+                Some(b) => { k -= 1; bs[k] = b; if b & 0xC0 != 0x80 { break } },
+                None => break,
+            }
+        }
+        if k == bs.len() { return self.2.take() }
+        let mut group = &bs[k..];
+        let r = decode(&mut group).map(|r| complete(bs[k], r, self.1.next));
+        self.1.next = Some(bs[k]);
+        if group.is_empty() { return r }
+        // The sequence begun at `bs[k]` ends short of the last byte, so that byte is a
+        // stray continuation byte, and the rest are left for later.
+        self.1 = Tail::new(&bs[k..bs.len()-1], Some(bs[bs.len()-1]));
+        Some(Err(DecodeError::invalid(ErrorKind::UnexpectedContinuation, 1)))
     }
 }
 
+#[inline]
 pub fn decode_slice_u32(bs: &[u8]) -> Option<(u32, NonZeroUsize)> {
-    let bs_l = bs.len();
-    let (&b0, bs) = bs.split_first()?;
+    decode_slice_u32_const(bs).map(|(x, l)| (x, unsafe { NonZeroUsize::new_unchecked(l) }))
+}
+
+/// `decode_slice_u32` for constant evaluation, with the same rules, but a plain length
+pub const fn decode_slice_u32_const(bs: &[u8]) -> Option<(u32, usize)> {
+    if bs.is_empty() { return None }
+    let b0 = bs[0];
     let l = (!b0).leading_zeros() as usize;
-    if l > bs_l { return None }
-    if 0 == l { return Some((b0 as _, unsafe { NonZeroUsize::new_unchecked(1) })); }
-    let l = NonZeroUsize::new(l)?;
-    let mut x = b0 as u32 & (0x7F >> l.get());
-    for b in bs.iter().cloned().take(l.get().wrapping_sub(1)) {
+    if 0 == l { return Some((b0 as _, 1)) }
+    if 1 == l || l > 4 { return None } // continuation byte or obsolete long form
+    if l > bs.len() { return None }
+    let mut x = b0 as u32 & (0x7F >> l);
+    let mut k = 1;
+    while k < l {
+        let b = bs[k];
+        if b & 0xC0 != 0x80 { return None }
         x <<= 6;
         x |= b as u32 & 0x3F;
+        k += 1;
     }
+    let m = if x < 0x80 { 1 } else if x < 0x800 { 2 } else if x < 0x10000 { 3 } else { 4 };
+    if m != l { return None } // overlong
     Some((x, l))
 }
 
+/// Decode the first character of `bs`, and return it with the number of bytes it takes;
+/// return `None` if `bs` doesn't begin with a whole valid character. Unlike
+/// `decode_slice_u32`, it rejects surrogates, such as `ED A0 80`.
 pub fn decode_slice(bs: &[u8]) -> Option<(char, NonZeroUsize)> {
     decode_slice_u32(bs).and_then(|(x, n)| from_u32(x).map(|x| (x, n)))
 }
 
-mod private {
-    pub trait UtfExtSealed {}
+/// Whether `c` encodes as UTF-8 to bytes which `decode_slice` decodes back to `c`, as
+/// should hold for every `char`, for fuzzing
+pub fn roundtrip_ok(c: char) -> bool {
+    let mut buf = [0; 4];
+    let bs = c.try_encode_utf8(&mut buf).map_or(&[][..], |s| s.as_bytes());
+    decode_slice(bs) == NonZeroUsize::new(bs.len()).map(|l| (c, l))
 }
-use private::*;
 
-pub trait UtfExt: UtfExtSealed {
-    type UtfSlice: ?Sized;
-    /// Encode the character into the given buffer; return `None` if the buffer is too short.
-    fn try_encode_utf8(self, bs: &mut [u8]) -> Option<&mut Self::UtfSlice>;
+/// Whether each character decoded from `bs` encodes as UTF-8 to the very bytes it was
+/// decoded from, and the invalid sequences take the rest, so that a valid `bs` re-encodes
+/// to itself, as should hold for all `bs`, for fuzzing
+pub fn decode_encode_stable(bs: &[u8]) -> bool {
+    let mut end = 0;
+    for (i, r) in char_indices(bs) {
+        if i != end { return false }
+        end = match r {
+            Ok(c) => {
+                let mut buf = [0; 4];
+                let s = c.try_encode_utf8(&mut buf).map_or(&[][..], |s| s.as_bytes());
+                if bs.get(i..i + s.len()) != Some(s) { return false }
+                i + s.len()
+            },
+            Err(e) => i + e.len(),
+        };
+    }
+    end == bs.len()
 }
 
-impl UtfExtSealed for char {}
-impl UtfExtSealed for u32 {}
-
-impl UtfExt for char {
-    type UtfSlice = str;
-    #[inline]
-    fn try_encode_utf8(self, bs: &mut [u8]) -> Option<&mut str> {
-        (self as u32).try_encode_utf8(bs).map(|bs| unsafe { str::from_utf8_unchecked_mut(bs) })
+/// `decode_slice` for constant evaluation, with a plain length
+pub const fn decode_slice_const(bs: &[u8]) -> Option<(char, usize)> {
+    match decode_slice_u32_const(bs) {
+        Some((x, l)) => match from_u32(x) { Some(c) => Some((c, l)), None => None },
+        None => None,
     }
 }
 
-impl UtfExt for u32 {
-    type UtfSlice = [u8];
-    fn try_encode_utf8(mut self, bs: &mut [u8]) -> Option<&mut [u8]> {
-        static ls: [Fin7; 33] = [F0, F6, F6, F6, F6, F6, F5, F5,
-                                 F5, F5, F5, F4, F4, F4, F4, F4,
-                                 F3, F3, F3, F3, F3, F2, F2, F2,
-                                 F2, F1, F1, F1, F1, F1, F1, F1, F1];
-        let l = ls[self.leading_zeros() as usize] as usize;
-        let first = !(!0u8 >> l);
-        Some({
-            let bs0 = bs.get_mut(0..l)?;
-            let (b0, bs) = bs0.split_first_mut()?;
-            for b in bs.iter_mut().rev() {
-                *b = self as u8 & 0x3F | 0x80;
-                self >>= 6;
-            }
-            *b0 = self as u8 | if l > 1 { first } else { 0 };
-            bs0
-        })
-    }
+/// Decode the last character of `bs`, and return it with the number of bytes it takes
+/// at the end; return `None` if the trailing bytes are not a whole valid character.
+pub fn decode_slice_back(bs: &[u8]) -> Option<(char, NonZeroUsize)> {
+    let k = bs.iter().rev().take(4).position(|&b| b & 0xC0 != 0x80)?;
+    let bs = &bs[bs.len() - 1 - k..];
+    decode_slice(bs).filter(|&(_, n)| n.get() == bs.len())
 }
 
-/// Kludge until we have a stable version of `::core::intrinsics::assume`
-#[derive(Clone, Copy)]
-#[repr(u8)]
-enum Fin7 { F0 = 0, F1 = 1, F2 = 2, F3 = 3, F4 = 4, F5 = 5, F6 = 6 }
-use self::Fin7::*;
+/// The last item decoding `bs` as UTF-8 yields: its last character, or the invalid
+/// sequence at its end, if any
+#[inline]
+pub fn last_char(bs: &[u8]) -> Option<Result<char, InvalidSequence>> {
+    iter_slice(bs).next_back()
+}
 
-#[test]
-fn test_decode() {
-    use std::vec::Vec;
-    use std::iter::FromIterator;
+/// An iterator over the characters of a byte slice as UTF-8, from the end
+#[derive(Clone, Debug)]
+pub struct RChars<'a>(SliceDecoder<'a>);
 
-    for &(str, bs) in [("", &[] as &[u8]),
-                       ("A", &[0x41u8] as &[u8]),
-                       ("�", &[0xC1u8, 0x81u8] as &[u8]),
-                       ("♥", &[0xE2u8, 0x99u8, 0xA5u8]),
-                       ("♥A", &[0xE2u8, 0x99u8, 0xA5u8, 0x41u8] as &[u8]),
-                       ("�", &[0xE2u8, 0x99u8] as &[u8]),
-                       ("�A", &[0xE2u8, 0x99u8, 0x41u8] as &[u8]),
-                       ("�", &[0xC0u8] as &[u8]),
-                       ("�A", &[0xC0u8, 0x41u8] as &[u8]),
-                       ("�", &[0x80u8] as &[u8]),
-                       ("�A", &[0x80u8, 0x41u8] as &[u8]),
-                       ("�", &[0xFEu8] as &[u8]),
-                       ("�A", &[0xFEu8, 0x41u8] as &[u8]),
-                       ("�", &[0xFFu8] as &[u8]),
-                       ("�A", &[0xFFu8, 0x41u8] as &[u8])].iter() {
-        assert!(Iterator::eq(str.chars(),
-                             decode_utf8(bs.into_iter().cloned())
-                                 .map(|r_b| r_b.unwrap_or('\u{FFFD}'))),
-                "chars = {}, bytes = {:?}, decoded = {:?}", str, bs,
-                Vec::from_iter(decode_utf8(bs.into_iter().cloned())
-                                   .map(|r_b| r_b.unwrap_or('\u{FFFD}'))));
-    }
+/// Decodes a byte slice as UTF-8 from its end, yielding what `iter_slice` does, in reverse,
+/// as `str::chars().rev()` does.
+#[inline]
+pub fn rchars(bs: &[u8]) -> RChars<'_> { RChars(iter_slice(bs)) }
+
+impl<'a> Iterator for RChars<'a> {
+    type Item = Result<char, InvalidSequence>;
+    #[inline]
+    fn next(&mut self) -> Option<Result<char, InvalidSequence>> { self.0.next_back() }
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) { self.0.size_hint() }
 }
 
-#[test]
-fn test_encode() {
-    for &(s, x) in
-      [ ("A", 'A')
-      , ("♥", '♥')
-      ].iter() {
-        let mut buf = [0u8; 6];
-        let ts = x.try_encode_utf8(&mut buf[..]).map(|x| x as &str);
-        assert_eq!(Some(s), ts, "{:02X?}", ts.map(str::as_bytes));
-    }
+impl<'a> iter::FusedIterator for RChars<'a> {}
+
+impl<'a> DoubleEndedIterator for RChars<'a> {
+    #[inline]
+    fn next_back(&mut self) -> Option<Result<char, InvalidSequence>> { self.0.next() }
+}
+
+impl<I: Iterator<Item = u8>> Iterator for DecodeUtf8Lossy<I> {
+    type Item = char;
+    #[inline]
+    fn next(&mut self) -> Option<char> { self.0.next().map(|r| r.unwrap_or(self.1)) }
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) { self.0.size_hint() }
+}
+
+impl<I: iter::FusedIterator<Item = u8>> iter::FusedIterator for DecodeUtf8Lossy<I> {}
+
+impl<I: DoubleEndedIterator<Item = u8>> DoubleEndedIterator for DecodeUtf8Lossy<I> {
+    #[inline]
+    fn next_back(&mut self) -> Option<char> { self.0.next_back().map(|r| r.unwrap_or(self.1)) }
+}
+
+impl<I: Iterator<Item = u8>> Iterator for DecodeUtf8Filter<I> {
+    type Item = char;
+    #[inline]
+    fn next(&mut self) -> Option<char> { self.0.by_ref().filter_map(Result::ok).next() }
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) { (0, self.0.size_hint().1) }
+}
+
+impl<I: iter::FusedIterator<Item = u8>> iter::FusedIterator for DecodeUtf8Filter<I> {}
+
+impl<I: DoubleEndedIterator<Item = u8>> DoubleEndedIterator for DecodeUtf8Filter<I> {
+    #[inline]
+    fn next_back(&mut self) -> Option<char> { self.0.by_ref().rev().filter_map(Result::ok).next() }
+}
+
+impl<I: Iterator<Item = u8>> Iterator for DecodeUtf8Strict<I> {
+    type Item = Result<char, DecodeError>;
+    #[inline]
+    fn next(&mut self) -> Option<Result<char, DecodeError>> { self.0.next().map(strict) }
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) { self.0.size_hint() }
+}
+
+impl<I: iter::FusedIterator<Item = u8>> iter::FusedIterator for DecodeUtf8Strict<I> {}
+
+impl<I: DoubleEndedIterator<Item = u8>> DoubleEndedIterator for DecodeUtf8Strict<I> {
+    #[inline]
+    fn next_back(&mut self) -> Option<Result<char, DecodeError>> { self.0.next_back().map(strict) }
+}
+
+impl<I: Iterator<Item = u8>> Iterator for DecodeUtf8NoControls<I> {
+    type Item = Result<char, DecodeError>;
+    #[inline]
+    fn next(&mut self) -> Option<Result<char, DecodeError>> {
+        let r = self.iter.next()?;
+        Some(self.check(r))
+    }
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) { self.iter.size_hint() }
+}
+
+impl<I: iter::FusedIterator<Item = u8>> iter::FusedIterator for DecodeUtf8NoControls<I> {}
+
+impl<I: DoubleEndedIterator<Item = u8>> DoubleEndedIterator for DecodeUtf8NoControls<I> {
+    #[inline]
+    fn next_back(&mut self) -> Option<Result<char, DecodeError>> {
+        let r = self.iter.next_back()?;
+        Some(self.check(r))
+    }
+}
+
+impl<I: Iterator<Item = u8>> Iterator for DecodeUtf8U32<I> {
+    type Item = Result<u32, InvalidSequence>;
+    #[inline]
+    fn next(&mut self) -> Option<Result<u32, InvalidSequence>> { decode_u32(&mut self.0, true) }
+}
+
+impl<I: iter::FusedIterator<Item = u8>> iter::FusedIterator for DecodeUtf8U32<I> {}
+
+/// An iterator over an iterator of bytes of the characters the bytes represent as
+/// UTF-8 but for U+0000, as `try_encode_utf8_modified` writes them
+#[derive(Clone, Debug)]
+pub struct DecodeUtf8Modified<I: Iterator<Item = u8>>(iter::Peekable<I>);
+
+/// Decodes an `Iterator` of bytes as UTF-8, but for U+0000, which must be in the overlong
+/// form `C0 80`; a 0 byte is invalid. Unlike `mutf8::decode_mutf8`, it takes supplementary
+/// characters in their 4-byte forms.
+#[inline]
+pub fn decode_utf8_modified<I: IntoIterator<Item = u8>>(i: I) -> DecodeUtf8Modified<I::IntoIter> {
+    DecodeUtf8Modified(i.into_iter().peekable())
+}
+
+impl<I: Iterator<Item = u8>> Iterator for DecodeUtf8Modified<I> {
+    type Item = Result<char, InvalidSequence>;
+    fn next(&mut self) -> Option<Result<char, InvalidSequence>> {
+        match decode_modified_nul(&mut self.0) {
+            Some(r) => Some(r.map(|_| '\0')),
+            None => decode(&mut self.0),
+        }
+    }
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) { (0, self.0.size_hint().1) }
+}
+
+/// Decode the next byte of `bs` if it's 0, which is invalid, or begins the overlong form
+/// of U+0000 which Modified UTF-8 has in its place; else return `None`, consuming nothing.
+fn decode_modified_nul<I: Iterator<Item = u8>>(bs: &mut iter::Peekable<I>)
+  -> Option<Result<u32, DecodeError>> {
+    match *bs.peek()? {
+        0 => {
+            bs.next();
+            Some(Err(DecodeError::invalid(ErrorKind::InvalidLeadByte, 1)))
+        },
+        0xC0 => {
+            bs.next();
+            Some(match bs.peek() {
+                Some(&0x80) => { bs.next(); Ok(0) },
+                Some(&b) if b & 0xC0 == 0x80 => Err(DecodeError::invalid(ErrorKind::Overlong, 1)),
+                Some(_) => Err(DecodeError::invalid(ErrorKind::TruncatedSequence, 1)),
+                None => Err(DecodeError::incomplete(2, 1)),
+            })
+        },
+        _ => None,
+    }
+}
+
+impl<I: iter::FusedIterator<Item = u8>> iter::FusedIterator for DecodeUtf8Modified<I> {}
+
+/// An iterator over an iterator of bytes of the code points the bytes represent as
+/// UTF-8 of RFC 2279, with its 5- and 6-byte forms
+#[derive(Clone, Debug)]
+pub struct DecodeUtf8Legacy<I: Iterator<Item = u8>>(iter::Peekable<I>);
+
+/// Decodes an `Iterator` of bytes as the original UTF-8, which encodes any code point up
+/// to U+7FFFFFFF, in up to 6 bytes, surrogates included, so a code point may not be a
+/// Unicode scalar value, and so may not be a `char`. Only overlong forms are invalid.
+#[inline]
+pub fn decode_utf8_legacy<I: IntoIterator<Item = u8>>(i: I) -> DecodeUtf8Legacy<I::IntoIter> {
+    DecodeUtf8Legacy(i.into_iter().peekable())
+}
+
+impl<I: Iterator<Item = u8>> Iterator for DecodeUtf8Legacy<I> {
+    type Item = Result<u32, InvalidSequence>;
+    #[inline]
+    fn next(&mut self) -> Option<Result<u32, InvalidSequence>> { decode_legacy(&mut self.0) }
+}
+
+impl<I: iter::FusedIterator<Item = u8>> iter::FusedIterator for DecodeUtf8Legacy<I> {}
+
+/// `decode_u32`, but of the original UTF-8
+fn decode_legacy<B: Bytes>(bs: &mut B) -> Option<Result<u32, DecodeError>> {
+    let b0 = bs.next_byte()?;
+    let l = match b0 {
+        0x00..=0x7F => return Some(Ok(b0 as u32)),
+        0xC2..=0xFD => (!b0).leading_zeros() as usize,
+        _ => return Some(Err(DecodeError::invalid(invalid_kind(b0, None), 1))),
+    };
+    let mut x = (b0 as u32) & (0x7F >> l);
+    for k in 1..l {
+        // Which excludes overlong forms
+        let lo = match (k, b0) { (1, 0xE0) => 0xA0, (1, 0xF0) => 0x90, (1, 0xF8) => 0x88,
+                                 (1, 0xFC) => 0x84, _ => 0x80 };
+        match bs.peek_byte() {
+            Some(b) if lo <= b && b <= 0xBF => {
+                bs.next_byte();
+                x = (x << 6) | (b as u32) & 0x3F;
+            },
+            Some(b) => {
+                let kind = if b & 0xC0 == 0x80 { ErrorKind::Overlong }
+                           else { ErrorKind::TruncatedSequence };
+                return Some(Err(DecodeError::invalid(kind, k)));
+            },
+            None => return Some(Err(DecodeError::incomplete(l, k))),
+        }
+    }
+    Some(Ok(x))
+}
+
+/// Decodes an `Iterator` of bytes as UTF-8 into a `String`, failing at the first
+/// invalid sequence.
+#[cfg(feature = "alloc")]
+pub fn decode_to_string<I: IntoIterator<Item = u8>>(i: I) -> Result<String, InvalidSequence> {
+    let i = i.into_iter();
+    let mut s = String::with_capacity(i.size_hint().0);
+    for r in decode_utf8(i) { s.push(r?); }
+    Ok(s)
+}
+
+/// Decodes an `Iterator` of bytes as UTF-8 into a `String`, replacing each maximal
+/// invalid subsequence with U+FFFD REPLACEMENT CHARACTER.
+#[cfg(feature = "alloc")]
+pub fn decode_to_string_lossy<I: IntoIterator<Item = u8>>(i: I) -> String {
+    let i = i.into_iter();
+    let mut s = String::with_capacity(i.size_hint().0);
+    s.extend(decode_utf8_lossy(i));
+    s
+}
+
+/// An iterator over an iterator of bytes of the lines of text the bytes represent as UTF-8
+#[cfg(feature = "alloc")]
+#[derive(Clone, Debug)]
+pub struct DecodeUtf8Lines<I: Iterator<Item = u8>>(DecodeUtf8<I>);
+
+/// Decodes an `Iterator` of bytes as UTF-8, as lines, as `str::lines` splits them: each
+/// ends with `\n` or `\r\n`, or the end of the input. A line with an invalid sequence is
+/// `Err` of the first.
+#[cfg(feature = "alloc")]
+#[inline]
+pub fn decode_utf8_lines<I: IntoIterator<Item = u8>>(i: I) -> DecodeUtf8Lines<I::IntoIter> {
+    DecodeUtf8Lines(decode_utf8(i))
+}
+
+#[cfg(feature = "alloc")]
+impl<I: Iterator<Item = u8>> Iterator for DecodeUtf8Lines<I> {
+    type Item = Result<String, InvalidSequence>;
+    fn next(&mut self) -> Option<Result<String, InvalidSequence>> {
+        let mut s = String::new();
+        let mut r = self.0.next()?;
+        loop {
+            match r {
+                Ok('\n') => {
+                    if s.ends_with('\r') { s.pop(); }
+                    break
+                },
+                Ok(c) => s.push(c),
+                Err(e) => {
+                    self.0.by_ref().find(|r| Ok('\n') == *r);
+                    return Some(Err(e))
+                },
+            }
+            r = match self.0.next() { Some(r) => r, None => break };
+        }
+        Some(Ok(s))
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<I: iter::FusedIterator<Item = u8>> iter::FusedIterator for DecodeUtf8Lines<I> {}
+
+/// Encodes an `Iterator` of characters as UTF-8 into a `Vec`.
+#[cfg(feature = "alloc")]
+#[inline]
+pub fn encode_to_vec<I: IntoIterator<Item = char>>(i: I) -> Vec<u8> {
+    encode_vec(i).unwrap_or_else(|_| unreachable!())
+}
+
+/// Encodes an `Iterator` of code points as UTF-8 into a `Vec`, as `u32::try_encode_utf8`
+/// does, so surrogates are encoded as such; fails with the index of the first value above
+/// `0x10FFFF`, which can't be encoded.
+#[cfg(feature = "alloc")]
+#[inline]
+pub fn encode_to_vec_u32<I: IntoIterator<Item = u32>>(i: I) -> Result<Vec<u8>, usize> { encode_vec(i) }
+
+/// Encode each item into a buffer, and append that to the `Vec`, which grows only as it
+/// must; fail with the index of the first item which can't be encoded.
+#[cfg(feature = "alloc")]
+fn encode_vec<A: UtfExt, I: IntoIterator<Item = A>>(i: I) -> Result<Vec<u8>, usize>
+  where A::UtfSlice: AsRef<[u8]> {
+    let i = i.into_iter();
+    let mut v = Vec::with_capacity(i.size_hint().0);
+    let mut buf = [0; MAX_UTF8_LEN];
+    for (k, x) in i.enumerate() {
+        v.extend_from_slice(x.try_encode_utf8(&mut buf).ok_or(k)?.as_ref());
+    }
+    Ok(v)
+}
+
+/// Decodes a byte slice as UTF-8, replacing each maximal invalid subsequence with
+/// U+FFFD REPLACEMENT CHARACTER, as `String::from_utf8_lossy` does, borrowing it if it's
+/// valid.
+#[cfg(feature = "alloc")]
+pub fn decode_utf8_lossy_cow(bs: &[u8]) -> Cow<'_, str> {
+    let i = validate_up_to(bs);
+    let valid = unsafe { str::from_utf8_unchecked(&bs[..i]) };
+    if i == bs.len() { return Cow::Borrowed(valid) }
+    let mut s = String::with_capacity(bs.len());
+    s.push_str(valid);
+    s.extend(iter_slice(&bs[i..]).map(|r| r.unwrap_or('\u{FFFD}')));
+    Cow::Owned(s)
+}
+
+/// A fixed-capacity buffer didn't have room for all the output.
+#[cfg(feature = "heapless")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct CapacityError;
+
+#[cfg(feature = "heapless")]
+impl fmt::Display for CapacityError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { f.write_str("insufficient capacity") }
+}
+
+#[cfg(all(feature = "heapless", feature = "std"))]
+impl core::error::Error for CapacityError {}
+
+/// `decode_to_heapless` returns this if the input is invalid, or too long.
+#[cfg(feature = "heapless")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum HeaplessDecodeError {
+    /// The input is malformed.
+    Decode(InvalidSequence),
+    /// The input is too long for the `heapless::String`.
+    Capacity(CapacityError),
+}
+
+#[cfg(feature = "heapless")]
+impl From<InvalidSequence> for HeaplessDecodeError {
+    #[inline]
+    fn from(e: InvalidSequence) -> Self { HeaplessDecodeError::Decode(e) }
+}
+
+#[cfg(feature = "heapless")]
+impl From<CapacityError> for HeaplessDecodeError {
+    #[inline]
+    fn from(e: CapacityError) -> Self { HeaplessDecodeError::Capacity(e) }
+}
+
+#[cfg(feature = "heapless")]
+impl fmt::Display for HeaplessDecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HeaplessDecodeError::Decode(ref e) => e.fmt(f),
+            HeaplessDecodeError::Capacity(ref e) => e.fmt(f),
+        }
+    }
+}
+
+#[cfg(all(feature = "heapless", feature = "std"))]
+impl core::error::Error for HeaplessDecodeError {}
+
+/// Decodes an `Iterator` of bytes as UTF-8 into a `heapless::String`, failing at the
+/// first invalid sequence, or character for which it has no room.
+#[cfg(feature = "heapless")]
+pub fn decode_to_heapless<const N: usize, I: IntoIterator<Item = u8>>(i: I)
+  -> Result<heapless::String<N>, HeaplessDecodeError> {
+    let mut s = heapless::String::new();
+    for r in decode_utf8(i) { s.push(r?).map_err(|()| CapacityError)?; }
+    Ok(s)
+}
+
+/// Encodes the characters as UTF-8 into a `heapless::String`, failing at the first for
+/// which it has no room.
+#[cfg(feature = "heapless")]
+pub fn encode_to_heapless<const N: usize, I: IntoIterator<Item = char>>(chars: I)
+  -> Result<heapless::String<N>, CapacityError> {
+    let mut s = heapless::String::new();
+    for c in chars { s.push(c).map_err(|()| CapacityError)?; }
+    Ok(s)
+}
+
+/// Decode the first character of `bs`, and return it with the rest of `bs`. An invalid
+/// sequence takes its maximal subpart, so that at least one byte is always taken.
+#[inline]
+pub fn next_char(mut bs: &[u8]) -> Option<(Result<char, InvalidSequence>, &[u8])> {
+    let r = decode(&mut bs)?;
+    Some((r, bs))
+}
+
+/// An iterator over the characters of a byte slice as UTF-8, and the byte offsets at
+/// which they start
+#[derive(Clone, Debug)]
+pub struct CharIndices<'a> { bs: &'a [u8], offset: usize }
+
+/// Decodes a byte slice as UTF-8, yielding each character or invalid sequence with its
+/// offset, like `str::char_indices`.
+#[inline]
+pub fn char_indices(bs: &[u8]) -> CharIndices<'_> { CharIndices { bs, offset: 0 } }
+
+impl<'a> Iterator for CharIndices<'a> {
+    type Item = (usize, Result<char, InvalidSequence>);
+    #[inline]
+    fn next(&mut self) -> Option<(usize, Result<char, InvalidSequence>)> {
+        let (l, offset) = (self.bs.len(), self.offset);
+        let r = decode(&mut self.bs)?;
+        self.offset += l - self.bs.len();
+        Some((offset, r))
+    }
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.bs.len().div_ceil(4), Some(self.bs.len()))
+    }
+}
+
+impl<'a> iter::FusedIterator for CharIndices<'a> {}
+
+/// An iterator over the characters of a byte slice as UTF-8
+#[derive(Clone, Debug)]
+pub struct SliceDecoder<'a> {
+    bs: &'a [u8],
+    offset: usize,
+    /// Byte which followed `bs` in the input, if any, which `next_back` has taken
+    next: Option<u8>,
+}
+
+/// Decodes a byte slice as UTF-8, as `decode_utf8` does an `Iterator` of bytes, but
+/// without the overhead of peeking.
+#[inline]
+pub fn iter_slice(bs: &[u8]) -> SliceDecoder<'_> { SliceDecoder { bs, offset: 0, next: None } }
+
+/// Decodes a byte slice as UTF-8 but for an incomplete sequence at its end, which it
+/// returns apart, for the caller to prepend to the next chunk of input. An invalid
+/// sequence at the end is left to the decoder.
+#[inline]
+pub fn decode_slice_resumable(bs: &[u8]) -> (SliceDecoder<'_>, &[u8]) {
+    let l = match last_char(bs) { Some(Err(e)) if e.is_incomplete() => e.len(), _ => 0 };
+    let (bs, tail) = bs.split_at(bs.len() - l);
+    (SliceDecoder { bs, offset: 0, next: tail.first().cloned() }, tail)
+}
+
+/// An iterator over the characters of a byte slice as UTF-8, up to a NUL terminator
+#[derive(Clone, Debug)]
+pub struct DecodeCStr<'a> { iter: SliceDecoder<'a>, terminated: bool }
+
+/// Decodes a byte slice as UTF-8 up to the first NUL byte, as a C string, and return also
+/// the offset of the NUL, if any. A sequence which the NUL cuts short is invalid, not
+/// incomplete.
+#[inline]
+pub fn decode_utf8_cstr(bs: &[u8]) -> (DecodeCStr<'_>, Option<usize>) {
+    let nul = bs.iter().position(|&b| 0 == b);
+    (DecodeCStr { iter: iter_slice(&bs[..nul.unwrap_or(bs.len())]), terminated: nul.is_some() }, nul)
+}
+
+impl<'a> Iterator for DecodeCStr<'a> {
+    type Item = Result<char, InvalidSequence>;
+    #[inline]
+    fn next(&mut self) -> Option<Result<char, InvalidSequence>> {
+        Some(match self.iter.next()? {
+            Err(e) if e.is_incomplete() && self.terminated =>
+                Err(DecodeError::invalid(ErrorKind::TruncatedSequence, e.len())),
+            r => r,
+        })
+    }
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) { self.iter.size_hint() }
+}
+
+impl<'a> iter::FusedIterator for DecodeCStr<'a> {}
+
+impl<'a> SliceDecoder<'a> {
+    /// Byte offset of the next item in the slice
+    #[inline]
+    pub fn offset(&self) -> usize { self.offset }
+
+    /// Check that the rest of the slice is valid UTF-8, and if so count its characters,
+    /// for an `ExactSizeIterator` over them; else fail with its first invalid sequence.
+    #[inline]
+    pub fn into_exact(self) -> Result<SliceChars<'a>, InvalidSequence> {
+        let len = self.clone().try_fold(0, |n, r| r.map(|_| n + 1))?;
+        Ok(SliceChars { iter: self, len })
+    }
+}
+
+impl<'a> Iterator for SliceDecoder<'a> {
+    type Item = Result<char, InvalidSequence>;
+    #[inline]
+    fn next(&mut self) -> Option<Result<char, InvalidSequence>> {
+        let (l, b0) = (self.bs.len(), *self.bs.first()?);
+        let r = decode(&mut self.bs)?;
+        self.offset += l - self.bs.len();
+        Some(complete(b0, r, self.next))
+    }
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.bs.len().div_ceil(4), Some(self.bs.len()))
+    }
+}
+
+impl<'a> iter::FusedIterator for SliceDecoder<'a> {}
+
+impl<'a> DoubleEndedIterator for SliceDecoder<'a> {
+    fn next_back(&mut self) -> Option<Result<char, InvalidSequence>> {
+        // Take the trailing run of continuation bytes, and the byte before it, which
+        // must begin whatever sequence the forward decoder would find there.
+        let l = self.bs.len();
+        let k = self.bs.iter().rev().take(4).position(|&b| b & 0xC0 != 0x80)
+                       .map_or(l.min(4), |k| k + 1);
+        let (bs, mut group) = self.bs.split_at(l - k);
+        let b0 = *group.first()?;
+        let r = complete(b0, decode(&mut group)?, self.next);
+        if group.is_empty() {
+            self.bs = bs;
+            self.next = Some(b0);
+            return Some(r)
+        }
+        // The sequence begun at the start of the group ends short of the last byte, so
+        // that byte is a stray continuation byte, and the rest are left for later.
+        self.next = Some(self.bs[l-1]);
+        self.bs = &self.bs[..l-1];
+        Some(Err(DecodeError::invalid(ErrorKind::UnexpectedContinuation, 1)))
+    }
+}
+
+/// An iterator over the characters of a valid UTF-8 byte slice, of known number
+#[derive(Clone, Debug)]
+pub struct SliceChars<'a> { iter: SliceDecoder<'a>, len: usize }
+
+impl<'a> Iterator for SliceChars<'a> {
+    type Item = char;
+    #[inline]
+    fn next(&mut self) -> Option<char> {
+        let c = self.iter.next()?.ok()?;
+        self.len -= 1;
+        Some(c)
+    }
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) { (self.len, Some(self.len)) }
+}
+
+impl<'a> iter::FusedIterator for SliceChars<'a> {}
+
+impl<'a> ExactSizeIterator for SliceChars<'a> {}
+
+impl<'a> DoubleEndedIterator for SliceChars<'a> {
+    #[inline]
+    fn next_back(&mut self) -> Option<char> {
+        let c = self.iter.next_back()?.ok()?;
+        self.len -= 1;
+        Some(c)
+    }
+}
+
+/// An iterator over an iterator of bytes of the characters the bytes represent as UTF-8,
+/// and the byte offsets at which they start
+#[derive(Clone, Debug)]
+pub struct DecodeUtf8Offsets<I: Iterator<Item = u8>> { iter: DecodeUtf8<I>, offset: usize }
+
+/// Decodes an `Iterator` of bytes as UTF-8, yielding each character or invalid sequence
+/// with its offset, as `char_indices` does for a slice.
+#[inline]
+pub fn decode_utf8_offsets<I: IntoIterator<Item = u8>>(i: I) -> DecodeUtf8Offsets<I::IntoIter> {
+    DecodeUtf8Offsets { iter: decode_utf8(i), offset: 0 }
+}
+
+impl<I: Iterator<Item = u8>> Iterator for DecodeUtf8Offsets<I> {
+    type Item = (usize, Result<char, InvalidSequence>);
+    #[inline]
+    fn next(&mut self) -> Option<(usize, Result<char, InvalidSequence>)> {
+        let offset = self.offset;
+        let r = self.iter.next()?;
+        self.offset += r.map_or_else(|e| e.len(), char::len_utf8);
+        Some((offset, r))
+    }
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) { self.iter.size_hint() }
+}
+
+impl<I: iter::FusedIterator<Item = u8>> iter::FusedIterator for DecodeUtf8Offsets<I> {}
+
+/// An iterator over the byte ranges of the pieces of decoded input between characters
+/// which match a predicate
+#[derive(Clone)]
+pub struct SplitOn<I: Iterator<Item = u8>, F> {
+    iter: DecodeUtf8Offsets<I>, pred: F, start: usize, finished: bool,
+}
+
+/// Splits decoded input on each character for which `pred` is true, as `str::split` does,
+/// yielding the `(start, end)` byte offsets of each piece. Invalid sequences never match,
+/// and so are left in the pieces.
+#[inline]
+pub fn split_on<I: Iterator<Item = u8>, F: FnMut(char) -> bool>(i: DecodeUtf8<I>, pred: F)
+  -> SplitOn<I, F> {
+    SplitOn { iter: DecodeUtf8Offsets { iter: i, offset: 0 }, pred, start: 0, finished: false }
+}
+
+impl<I: Iterator<Item = u8> + fmt::Debug, F> fmt::Debug for SplitOn<I, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SplitOn").field("iter", &self.iter).field("start", &self.start)
+         .field("finished", &self.finished).finish()
+    }
+}
+
+impl<I: Iterator<Item = u8>, F: FnMut(char) -> bool> Iterator for SplitOn<I, F> {
+    type Item = (usize, usize);
+    #[inline]
+    fn next(&mut self) -> Option<(usize, usize)> {
+        if self.finished { return None; }
+        let start = self.start;
+        for (i, r) in self.iter.by_ref() {
+            if let Ok(c) = r { if (self.pred)(c) {
+                self.start = i + c.len_utf8();
+                return Some((start, i));
+            } }
+        }
+        self.finished = true;
+        Some((start, self.iter.offset))
+    }
+}
+
+impl<I: Iterator<Item = u8>, F: FnMut(char) -> bool> iter::FusedIterator for SplitOn<I, F> {}
+
+/// An iterator over an iterator of bytes of the characters the bytes represent as UTF-8,
+/// and the numbers of bytes they take
+#[derive(Clone, Debug)]
+pub struct DecodeUtf8WithLen<I: Iterator<Item = u8>>(DecodeUtf8<I>);
+
+/// Decodes an `Iterator` of bytes as UTF-8, yielding each character with the number of
+/// bytes it was decoded from, which is always its shortest form, as only that is valid.
+#[inline]
+pub fn decode_utf8_with_len<I: IntoIterator<Item = u8>>(i: I) -> DecodeUtf8WithLen<I::IntoIter> {
+    DecodeUtf8WithLen(decode_utf8(i))
+}
+
+/// The decoder rejects every form of a character but the shortest, so the bytes it took
+/// are as many as `len_utf8` counts.
+#[inline]
+fn with_len(r: Result<char, DecodeError>) -> Result<(char, NonZeroUsize), DecodeError> {
+    r.map(|c| (c, NonZeroUsize::new(c.len_utf8()).unwrap()))
+}
+
+impl<I: Iterator<Item = u8>> Iterator for DecodeUtf8WithLen<I> {
+    type Item = Result<(char, NonZeroUsize), InvalidSequence>;
+    #[inline]
+    fn next(&mut self) -> Option<Result<(char, NonZeroUsize), InvalidSequence>> {
+        self.0.next().map(with_len)
+    }
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) { self.0.size_hint() }
+}
+
+impl<I: iter::FusedIterator<Item = u8>> iter::FusedIterator for DecodeUtf8WithLen<I> {}
+
+impl<I: DoubleEndedIterator<Item = u8>> DoubleEndedIterator for DecodeUtf8WithLen<I> {
+    #[inline]
+    fn next_back(&mut self) -> Option<Result<(char, NonZeroUsize), InvalidSequence>> {
+        self.0.next_back().map(with_len)
+    }
+}
+
+/// Range of code points in which a character is
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ScalarClass {
+    /// U+0000 to U+007F
+    Ascii,
+    /// U+0080 to U+00FF
+    Latin1,
+    /// U+0100 to U+FFFF
+    Bmp,
+    /// U+10000 to U+10FFFF
+    Astral,
+}
+
+impl ScalarClass {
+    /// The range in which `c` is
+    #[inline]
+    pub const fn of(c: char) -> Self {
+        match c as u32 {
+            0..=0x7F => ScalarClass::Ascii,
+            0x80..=0xFF => ScalarClass::Latin1,
+            0x100..=0xFFFF => ScalarClass::Bmp,
+            _ => ScalarClass::Astral,
+        }
+    }
+}
+
+/// An iterator over an iterator of bytes of the characters the bytes represent as UTF-8,
+/// and the ranges of code points in which they are
+#[derive(Clone, Debug)]
+pub struct DecodeUtf8Classified<I: Iterator<Item = u8>>(DecodeUtf8<I>);
+
+/// Decodes an `Iterator` of bytes as UTF-8, yielding each character with the range of
+/// code points in which it is.
+#[inline]
+pub fn decode_utf8_classified<I: IntoIterator<Item = u8>>(i: I) -> DecodeUtf8Classified<I::IntoIter> {
+    DecodeUtf8Classified(decode_utf8(i))
+}
+
+#[inline]
+fn classified(r: Result<char, DecodeError>) -> Result<(char, ScalarClass), DecodeError> {
+    r.map(|c| (c, ScalarClass::of(c)))
+}
+
+impl<I: Iterator<Item = u8>> Iterator for DecodeUtf8Classified<I> {
+    type Item = Result<(char, ScalarClass), InvalidSequence>;
+    #[inline]
+    fn next(&mut self) -> Option<Result<(char, ScalarClass), InvalidSequence>> {
+        self.0.next().map(classified)
+    }
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) { self.0.size_hint() }
+}
+
+impl<I: iter::FusedIterator<Item = u8>> iter::FusedIterator for DecodeUtf8Classified<I> {}
+
+impl<I: DoubleEndedIterator<Item = u8>> DoubleEndedIterator for DecodeUtf8Classified<I> {
+    #[inline]
+    fn next_back(&mut self) -> Option<Result<(char, ScalarClass), InvalidSequence>> {
+        self.0.next_back().map(classified)
+    }
+}
+
+/// Count the characters of `bs`, which must be valid UTF-8, by counting the bytes which
+/// aren't continuation bytes; the count for invalid input is meaningless.
+#[inline]
+pub fn count_chars(bs: &[u8]) -> usize {
+    let mut n = 0;
+    let mut i = 0;
+    while i < bs.len() {
+        let l = ascii::ascii_len(&bs[i..]);
+        n += l;
+        i += l;
+        while i < bs.len() && bs[i] >= 0x80 {
+            if bs[i] & 0xC0 != 0x80 { n += 1; }
+            i += 1;
+        }
+    }
+    n
+}
+
+/// Count the characters of `bs` as UTF-8, failing at the first invalid sequence.
+pub fn try_count_chars(mut bs: &[u8]) -> Result<usize, InvalidSequence> {
+    let mut n = 0;
+    while let Some(r) = decode(&mut bs) { r?; n += 1; }
+    Ok(n)
+}
+
+/// Number of UTF-16 code units of `c`
+#[inline]
+pub const fn char_utf16_len(c: char) -> usize { if (c as u32) < 0x10000 { 1 } else { 2 } }
+
+/// Count the UTF-16 code units of the characters of `bs` as UTF-8, failing at the first
+/// invalid sequence.
+pub fn utf16_len(mut bs: &[u8]) -> Result<usize, InvalidSequence> {
+    let mut n = 0;
+    loop {
+        let l = ascii::ascii_len(bs);
+        n += l;
+        bs = &bs[l..];
+        match decode(&mut bs) {
+            None => return Ok(n),
+            Some(r) => n += char_utf16_len(r?),
+        }
+    }
+}
+
+/// Measurements of a buffer of UTF-8 which `utf8_metrics` takes in one pass
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Utf8Metrics {
+    /// Number of characters, in all of the buffer
+    pub chars: usize,
+    /// Length in bytes of the valid prefix
+    pub bytes_valid: usize,
+    /// Number of ASCII characters
+    pub ascii: usize,
+    /// Number of maximal invalid subparts, each of which lossy decoding replaces
+    pub replacement_needed: usize,
+}
+
+/// Measure `bs` as UTF-8, in one pass.
+pub fn utf8_metrics(bs: &[u8]) -> Utf8Metrics {
+    let mut m = Utf8Metrics { bytes_valid: bs.len(), ..Utf8Metrics::default() };
+    let mut rest = bs;
+    loop {
+        let l = ascii::ascii_len(rest);
+        m.chars += l;
+        m.ascii += l;
+        rest = &rest[l..];
+        match decode(&mut rest) {
+            None => return m,
+            Some(Ok(c)) => { m.chars += 1; m.ascii += c.is_ascii() as usize; },
+            Some(Err(e)) => {
+                if 0 == m.replacement_needed { m.bytes_valid = bs.len() - rest.len() - e.len(); }
+                m.replacement_needed += 1;
+            },
+        }
+    }
+}
+
+/// Decode `bs` as UTF-8 into `out`, failing at the first invalid sequence; return the
+/// number of bytes decoded and of characters written. If `out` fills first, decoding stops
+/// there, at a character boundary, and the rest of `bs` is left unread and unchecked.
+pub fn decode_into(bs: &[u8], out: &mut [char]) -> Result<(usize, usize), InvalidSequence> {
+    let mut rest = bs;
+    let mut n = 0;
+    for c in out.iter_mut() {
+        match decode(&mut rest) {
+            Some(r) => *c = r?,
+            None => break,
+        }
+        n += 1;
+    }
+    Ok((bs.len() - rest.len(), n))
+}
+
+/// Decode `bs` as UTF-8 into `out`, re-encoded, with U+FFFD REPLACEMENT CHARACTER in place
+/// of each maximal invalid subsequence; return the number of bytes decoded and written.
+/// What is written is valid UTF-8. Decoding stops at the first character which doesn't
+/// fit in the rest of `out`, or invalid sequence whose 3-byte replacement doesn't, so
+/// neither is decoded, nor any of the rest of `bs`.
+pub fn decode_lossy_into(bs: &[u8], out: &mut [u8]) -> (usize, usize) {
+    let mut it = iter_slice(bs);
+    let mut l = 0;
+    loop {
+        let i = it.offset();
+        let c = match it.next() { Some(r) => r.unwrap_or('\u{FFFD}'), None => return (i, l) };
+        match c.try_encode_utf8(&mut out[l..]) {
+            Some(s) => l += s.len(),
+            None => return (i, l),
+        }
+    }
+}
+
+/// Whether `bs` is valid UTF-8
+#[inline]
+pub fn validate(bs: &[u8]) -> bool { validate_up_to(bs) == bs.len() }
+
+/// Split `bs` into its longest valid prefix, as a `str`, and the rest, which begins with
+/// an invalid or incomplete sequence unless empty
+#[inline]
+pub fn split_valid(bs: &[u8]) -> (&str, &[u8]) {
+    let (valid, rest) = bs.split_at(validate_up_to(bs));
+    (unsafe { str::from_utf8_unchecked(valid) }, rest)
+}
+
+/// Split `bs` after its first `n` characters, or all of them if fewer, into those, as a
+/// `str`, and the rest, unchecked; fail if an invalid sequence is among them.
+#[inline]
+pub fn take_chars(bs: &[u8], n: usize) -> Result<(&str, &[u8]), InvalidSequence> {
+    let mut rest = bs;
+    for _ in 0..n { if decode(&mut rest).transpose()?.is_none() { break } }
+    let (s, rest) = bs.split_at(bs.len() - rest.len());
+    Ok((unsafe { str::from_utf8_unchecked(s) }, rest))
+}
+
+/// Length of the maximal invalid subpart with which `bs` begins, which decoding takes for
+/// one invalid sequence, or `None` if `bs` begins with a valid character or a prefix of one
+/// cut short by its end, or is empty
+#[inline]
+pub fn invalid_subpart_len(mut bs: &[u8]) -> Option<NonZeroUsize> {
+    match decode(&mut bs)? {
+        Err(e) if !e.is_incomplete() => NonZeroUsize::new(e.len()),
+        _ => None,
+    }
+}
+
+/// Count the maximal invalid subparts of `bs`, and so the replacement characters which
+/// lossy decoding would put in their places, an incomplete sequence at its end included
+#[inline]
+pub fn count_invalid_subparts(mut bs: &[u8]) -> usize {
+    let mut n = 0;
+    loop {
+        bs = &bs[validate_up_to(bs)..];
+        if bs.is_empty() { return n; }
+        n += 1;
+        match invalid_subpart_len(bs) { Some(l) => bs = &bs[l.get()..], None => return n }
+    }
+}
+
+/// Whether `bs` decodes as UTF-8 to exactly `s`, which, as a character has but one valid
+/// encoding, is whether they're the same bytes
+#[inline]
+pub fn utf8_eq(bs: &[u8], s: &str) -> bool { bs == s.as_bytes() }
+
+/// Whether `bs` decodes as UTF-8 to exactly `s` with U+FFFD REPLACEMENT CHARACTER in place
+/// of each maximal invalid subsequence, as `decode_utf8_lossy` does
+#[inline]
+pub fn utf8_eq_lossy(bs: &[u8], s: &str) -> bool {
+    iter_slice(bs).map(|r| r.unwrap_or('\u{FFFD}')).eq(s.chars())
+}
+
+/// Bytes which are meant to be UTF-8 text, which compare equal to a `str` only if valid,
+/// and display as decoded lossily
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Utf8<'a>(pub &'a [u8]);
+
+impl<'a> PartialEq<str> for Utf8<'a> {
+    #[inline]
+    fn eq(&self, s: &str) -> bool { utf8_eq(self.0, s) }
+}
+
+impl<'a, 'b> PartialEq<&'b str> for Utf8<'a> {
+    #[inline]
+    fn eq(&self, s: &&'b str) -> bool { utf8_eq(self.0, s) }
+}
+
+impl<'a> fmt::Display for Utf8<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut bs = self.0;
+        loop {
+            let (s, rest) = split_valid(bs);
+            f.write_str(s)?;
+            if rest.is_empty() { return Ok(()); }
+            f.write_str("\u{FFFD}")?;
+            bs = &rest[invalid_subpart_len(rest).map_or(rest.len(), NonZeroUsize::get)..];
+        }
+    }
+}
+
+/// Whether `bs` is all ASCII, checked many bytes at a time
+#[inline]
+pub fn is_ascii(bs: &[u8]) -> bool { ascii::ascii_len(bs) == bs.len() }
+
+/// Offset of the first invalid sequence of `bs` as UTF-8, or `bs.len()` if there is none
+#[inline]
+pub fn validate_up_to(bs: &[u8]) -> usize { first_invalid(bs).map_or(bs.len(), |(i, _, _)| i) }
+
+/// Check that `bs` is valid UTF-8, or find the offset of its first invalid sequence, and
+/// why it's invalid
+#[inline]
+pub fn validate_detailed(bs: &[u8]) -> Result<(), (usize, ErrorKind)> {
+    first_invalid(bs).map_or(Ok(()), |(i, _, e)| Err((i, e.kind())))
+}
+
+/// Check again that a `str` is valid UTF-8, as `validate_detailed` does bytes. A sound
+/// `str` always is; this is to catch one made unchecked of bytes which weren't.
+#[inline]
+pub fn revalidate(s: &str) -> Result<(), (usize, ErrorKind)> { validate_detailed(s.as_bytes()) }
+
+/// Offset and length of the first invalid sequence of `bs`, and why it's invalid
+fn first_invalid(bs: &[u8]) -> Option<(usize, usize, DecodeError)> {
+    let mut state = dfa::ACCEPT;
+    let mut start = 0;
+    let mut i = 0;
+    while i < bs.len() {
+        if dfa::ACCEPT == state {
+            if bs[i] < 0x80 {
+                i += ascii::ascii_len(&bs[i..]);
+                continue;
+            }
+            start = i;
+        }
+        state = dfa::validate_step(state, bs[i]);
+        if dfa::REJECT == state {
+            // A byte which the automaton rejects after the lead byte begins the next sequence.
+            let (l, b) = if i == start { (1, None) } else { (i - start, Some(bs[i])) };
+            return Some((start, l, DecodeError::invalid(invalid_kind(bs[start], b), l)));
+        }
+        i += 1;
+    }
+    if dfa::ACCEPT == state { return None }
+    let got = bs.len() - start;
+    Some((start, got, DecodeError::incomplete(utf8_char_width(bs[start]), got)))
+}
+
+/// `from_utf8` returns this for invalid input.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Utf8Error { valid_up_to: usize, error_len: Option<usize> }
+
+impl Utf8Error {
+    /// Length of the valid prefix of the input
+    #[inline]
+    pub fn valid_up_to(&self) -> usize { self.valid_up_to }
+
+    /// Length of the invalid sequence after the valid prefix, or `None` if the input ended
+    /// in the midst of a sequence, which more input could yet complete
+    #[inline]
+    pub fn error_len(&self) -> Option<usize> { self.error_len }
+}
+
+impl fmt::Display for Utf8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.error_len {
+            Some(l) => write!(f, "invalid UTF-8 sequence of {} bytes from index {}", l, self.valid_up_to),
+            None => write!(f, "incomplete UTF-8 byte sequence from index {}", self.valid_up_to),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl core::error::Error for Utf8Error {}
+
+/// Convert `bs` to a `str` if it's valid UTF-8, like `core::str::from_utf8`.
+pub fn from_utf8(bs: &[u8]) -> Result<&str, Utf8Error> {
+    match first_invalid(bs) {
+        None => Ok(unsafe { str::from_utf8_unchecked(bs) }),
+        Some((valid_up_to, l, e)) => Err(Utf8Error {
+            valid_up_to,
+            error_len: if e.is_incomplete() { None } else { Some(l) },
+        }),
+    }
+}
+
+/// Whether `index` is at a character boundary of `bs` as UTF-8, like
+/// `str::is_char_boundary`: the start and end are, and an index beyond the end is not.
+#[inline]
+pub fn is_char_boundary(bs: &[u8], index: usize) -> bool {
+    0 == index || index == bs.len() || bs.get(index).is_some_and(|&b| b & 0xC0 != 0x80)
+}
+
+/// The greatest character boundary of `bs` at or before `index`, which may be beyond the
+/// end
+#[inline]
+pub fn floor_char_boundary(bs: &[u8], index: usize) -> usize {
+    let mut i = Ord::min(index, bs.len());
+    while !is_char_boundary(bs, i) { i -= 1; }
+    i
+}
+
+/// The least character boundary of `bs` at or after `index`, or the end if `index` is
+/// beyond it
+#[inline]
+pub fn ceil_char_boundary(bs: &[u8], index: usize) -> usize {
+    let mut i = Ord::min(index, bs.len());
+    while !is_char_boundary(bs, i) { i += 1; }
+    i
+}
+
+/// The longest prefix of `bs` at most `max_len` bytes long which ends at a character
+/// boundary
+#[inline]
+pub fn truncate_to_char_boundary(bs: &[u8], max_len: usize) -> &[u8] {
+    &bs[..floor_char_boundary(bs, max_len)]
+}
+
+/// A byte-order mark, and so the encoding it marks
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Bom { Utf8, Utf16Le, Utf16Be, Utf32Le, Utf32Be }
+
+/// Detect a byte-order mark at the start of `bs`, and return it with its length.
+/// `FF FE 00 00` is taken to mark UTF-32LE rather than UTF-16LE text starting with U+0000.
+pub fn detect_bom(bs: &[u8]) -> Option<(Bom, usize)> {
+    match *bs {
+        [0xEF, 0xBB, 0xBF, ..] => Some((Bom::Utf8, 3)),
+        [0xFF, 0xFE, 0x00, 0x00, ..] => Some((Bom::Utf32Le, 4)),
+        [0x00, 0x00, 0xFE, 0xFF, ..] => Some((Bom::Utf32Be, 4)),
+        [0xFF, 0xFE, ..] => Some((Bom::Utf16Le, 2)),
+        [0xFE, 0xFF, ..] => Some((Bom::Utf16Be, 2)),
+        _ => None,
+    }
+}
+
+/// Strip a leading UTF-8 byte-order mark from `bs`, if any.
+#[inline]
+pub fn strip_bom(bs: &[u8]) -> &[u8] {
+    match detect_bom(bs) { Some((Bom::Utf8, l)) => &bs[l..], _ => bs }
+}
+
+/// Deserialize a byte buffer as UTF-8 into a `String`, replacing each maximal invalid
+/// subsequence with U+FFFD REPLACEMENT CHARACTER; meant for `#[serde(deserialize_with)]`.
+#[cfg(all(feature = "serde", feature = "alloc"))]
+pub fn deserialize_utf8_lossy<'de, D: serde::Deserializer<'de>>(d: D) -> Result<String, D::Error> {
+    use serde::de;
+
+    struct Visitor;
+    impl<'de> de::Visitor<'de> for Visitor {
+        type Value = String;
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result { f.write_str("a byte buffer") }
+        fn visit_bytes<E: de::Error>(self, bs: &[u8]) -> Result<String, E> {
+            Ok(decode_to_string_lossy(bs.iter().cloned()))
+        }
+        fn visit_str<E: de::Error>(self, s: &str) -> Result<String, E> { Ok(s.into()) }
+        fn visit_seq<A: de::SeqAccess<'de>>(self, mut a: A) -> Result<String, A::Error> {
+            // The input gives the hint, so trust it no further than serde does.
+            let mut bs = alloc::vec::Vec::with_capacity(a.size_hint().map_or(0, |n| n.min(4096)));
+            while let Some(b) = a.next_element()? { bs.push(b); }
+            Ok(decode_to_string_lossy(bs))
+        }
+    }
+    d.deserialize_bytes(Visitor)
+}
+
+/// A decoder of UTF-8 arriving in chunks, between which characters may be split
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StreamDecoder { bs: [u8; 4], l: u8 }
+
+impl StreamDecoder {
+    #[inline]
+    pub fn new() -> Self { Self::default() }
+
+    /// Decode the next chunk of input. An incomplete sequence at the end of the chunk is
+    /// held to be completed by the next one. If the iterator is dropped before its end,
+    /// feed again the rest of the chunk, from `Feed::consumed`.
+    #[inline]
+    pub fn feed<'a>(&'a mut self, chunk: &'a [u8]) -> Feed<'a> {
+        Feed { decoder: self, chunk, pos: 0 }
+    }
+
+    /// Finish decoding; return the error for a sequence left incomplete at the end of
+    /// input, if any.
+    #[inline]
+    pub fn finish(self) -> Option<InvalidSequence> {
+        if 0 == self.l { return None }
+        Some(DecodeError::incomplete(utf8_char_width(self.bs[0]), self.l as usize))
+    }
+}
+
+/// An iterator over the characters of a chunk fed to a `StreamDecoder`
+#[derive(Debug)]
+pub struct Feed<'a> { decoder: &'a mut StreamDecoder, chunk: &'a [u8], pos: usize }
+
+impl<'a> Feed<'a> {
+    /// Number of bytes of the chunk taken so far, decoded or held by the decoder
+    #[inline]
+    pub fn consumed(&self) -> usize { self.pos }
+}
+
+impl<'a> Iterator for Feed<'a> {
+    type Item = Result<char, InvalidSequence>;
+    fn next(&mut self) -> Option<Result<char, InvalidSequence>> {
+        let d = &mut *self.decoder;
+        let l = d.l as usize;
+        if l > 0 {
+            // Top up the held sequence from the chunk, and give back what it doesn't take.
+            let n = Ord::min(d.bs.len() - l, self.chunk.len() - self.pos);
+            d.bs[l..l+n].copy_from_slice(&self.chunk[self.pos..self.pos+n]);
+            self.pos += n;
+            let mut bs = &d.bs[..l+n];
+            match decode(&mut bs)? {
+                Err(ref e) if e.is_incomplete() => { d.l = (l+n) as u8; None },
+                r => { self.pos -= bs.len(); d.l = 0; Some(r) },
+            }
+        } else {
+            let mut bs = &self.chunk[self.pos..];
+            match decode(&mut bs)? {
+                Err(ref e) if e.is_incomplete() => {
+                    let bs = &self.chunk[self.pos..];
+                    d.bs[..bs.len()].copy_from_slice(bs);
+                    d.l = bs.len() as u8;
+                    self.pos = self.chunk.len();
+                    None
+                },
+                r => { self.pos = self.chunk.len() - bs.len(); Some(r) },
+            }
+        }
+    }
+}
+
+/// A decoder of UTF-8 arriving a byte at a time
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PushDecoder { bs: [u8; 4], l: u8 }
+
+impl PushDecoder {
+    #[inline]
+    pub fn new() -> Self { Self::default() }
+
+    /// Decode the next byte of input: return the items which it resolves, none if it leaves
+    /// a sequence incomplete. A byte which ends an invalid sequence without being part of
+    /// it may be a whole item itself, so it may resolve two.
+    pub fn push(&mut self, byte: u8) -> Pushed {
+        let l = self.l as usize;
+        self.bs[l] = byte;
+        let mut bs = &self.bs[..l+1];
+        let r = match decode(&mut bs) {
+            Some(Err(ref e)) if e.is_incomplete() => { self.l += 1; return Pushed(None, None) },
+            r => r,
+        };
+        self.l = 0;
+        // What the item doesn't take, if anything, is the byte just pushed.
+        if bs.is_empty() { return Pushed(r, None) }
+        self.bs[0] = byte;
+        Pushed(r, match decode(&mut &self.bs[..1]) {
+            Some(Err(ref e)) if e.is_incomplete() => { self.l = 1; None },
+            r => r,
+        })
+    }
+
+    /// Finish decoding: return the error for a sequence left incomplete at the end of
+    /// input, if any.
+    #[inline]
+    pub fn flush(&mut self) -> Option<Result<char, InvalidSequence>> {
+        if 0 == self.l { return None }
+        let l = core::mem::replace(&mut self.l, 0) as usize;
+        Some(Err(DecodeError::incomplete(utf8_char_width(self.bs[0]), l)))
+    }
+}
+
+/// The items which `PushDecoder::push` resolves, at most two
+#[derive(Clone, Debug)]
+pub struct Pushed(Option<Result<char, InvalidSequence>>, Option<Result<char, InvalidSequence>>);
+
+impl Iterator for Pushed {
+    type Item = Result<char, InvalidSequence>;
+    #[inline]
+    fn next(&mut self) -> Option<Result<char, InvalidSequence>> { self.0.take().or_else(|| self.1.take()) }
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let n = self.0.is_some() as usize + self.1.is_some() as usize;
+        (n, Some(n))
+    }
+}
+
+impl iter::FusedIterator for Pushed {}
+
+impl ExactSizeIterator for Pushed {}
+
+/// Bytes read for decoding, and the decoder of them, shared by the adaptors of readers
+#[cfg(any(feature = "std", feature = "embedded-io"))]
+#[derive(Debug)]
+struct ReadBuf { buf: [u8; 256], pos: usize, end: usize, decoder: StreamDecoder, eof: bool }
+
+#[cfg(any(feature = "std", feature = "embedded-io"))]
+impl ReadBuf {
+    #[inline]
+    fn new() -> Self {
+        ReadBuf { buf: [0; 256], pos: 0, end: 0, decoder: StreamDecoder::new(), eof: false }
+    }
+
+    /// Decode the next character, calling `read` to refill the buffer as needed, and
+    /// again if `retry` deems its error transient.
+    fn next<E, F: FnMut(&mut [u8]) -> Result<usize, E>>(&mut self, mut read: F, retry: fn(&E) -> bool)
+      -> Option<Result<Result<char, InvalidSequence>, E>> {
+        loop {
+            let mut feed = self.decoder.feed(&self.buf[self.pos..self.end]);
+            let r = feed.next();
+            self.pos += feed.consumed();
+            if let Some(r) = r { return Some(Ok(r)) }
+            if self.eof {
+                let r = self.decoder.finish();
+                self.decoder = StreamDecoder::new();
+                return r.map(|e| Ok(Err(e)));
+            }
+            match read(&mut self.buf) {
+                Ok(0) => self.eof = true,
+                Ok(n) => { self.pos = 0; self.end = n; },
+                Err(ref e) if retry(e) => (),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// An iterator over the characters of a reader of UTF-8
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct DecodeRead<R: std::io::Read> { r: R, buf: ReadBuf }
+
+/// Decodes a reader of bytes as UTF-8, reading as it goes.
+#[cfg(feature = "std")]
+#[inline]
+pub fn decode_reader<R: std::io::Read>(r: R) -> DecodeRead<R> { DecodeRead { r, buf: ReadBuf::new() } }
+
+#[cfg(feature = "std")]
+impl<R: std::io::Read> Iterator for DecodeRead<R> {
+    type Item = std::io::Result<Result<char, InvalidSequence>>;
+    #[inline]
+    fn next(&mut self) -> Option<std::io::Result<Result<char, InvalidSequence>>> {
+        let r = &mut self.r;
+        self.buf.next(|buf| r.read(buf), |e| std::io::ErrorKind::Interrupted == e.kind())
+    }
+}
+
+/// An error of decoding an `embedded_io::Read`er as UTF-8
+#[cfg(feature = "embedded-io")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EmbeddedError<E> {
+    /// The reader failed.
+    Io(E),
+    /// The input is malformed.
+    Decode(InvalidSequence),
+}
+
+#[cfg(feature = "embedded-io")]
+impl<E> From<InvalidSequence> for EmbeddedError<E> {
+    #[inline]
+    fn from(e: InvalidSequence) -> Self { EmbeddedError::Decode(e) }
+}
+
+#[cfg(feature = "embedded-io")]
+impl<E: embedded_io::Error> embedded_io::Error for EmbeddedError<E> {
+    #[inline]
+    fn kind(&self) -> embedded_io::ErrorKind {
+        match *self {
+            EmbeddedError::Io(ref e) => e.kind(),
+            EmbeddedError::Decode(_) => embedded_io::ErrorKind::InvalidData,
+        }
+    }
+}
+
+/// An iterator over the characters of an `embedded_io::Read`er of UTF-8
+#[cfg(feature = "embedded-io")]
+#[derive(Debug)]
+pub struct DecodeEmbedded<R: embedded_io::Read> { r: R, buf: ReadBuf }
+
+/// Decodes an `embedded_io::Read`er of bytes as UTF-8, reading as it goes, as
+/// `decode_reader` does a `std::io::Read`er.
+#[cfg(feature = "embedded-io")]
+#[inline]
+pub fn decode_embedded<R: embedded_io::Read>(r: R) -> DecodeEmbedded<R> {
+    DecodeEmbedded { r, buf: ReadBuf::new() }
+}
+
+#[cfg(feature = "embedded-io")]
+impl<R: embedded_io::Read> Iterator for DecodeEmbedded<R> {
+    type Item = Result<char, EmbeddedError<R::Error>>;
+    #[inline]
+    fn next(&mut self) -> Option<Result<char, EmbeddedError<R::Error>>> {
+        use embedded_io::Error;
+
+        let r = &mut self.r;
+        let retry = |e: &R::Error| embedded_io::ErrorKind::Interrupted == e.kind();
+        Some(match self.buf.next(|buf| r.read(buf), retry)? {
+            Ok(r) => r.map_err(EmbeddedError::Decode),
+            Err(e) => Err(EmbeddedError::Io(e)),
+        })
+    }
+}
+
+/// An iterator over the characters of a `bytes::Buf` of UTF-8
+#[cfg(feature = "bytes")]
+#[derive(Clone, Debug)]
+pub struct DecodeBuf<B: bytes::Buf>(B);
+
+/// Decodes a `bytes::Buf` as UTF-8, advancing it past each character as it goes.
+#[cfg(feature = "bytes")]
+#[inline]
+pub fn decode_buf<B: bytes::Buf>(buf: B) -> DecodeBuf<B> { DecodeBuf(buf) }
+
+#[cfg(feature = "bytes")]
+impl<B: bytes::Buf> DecodeBuf<B> {
+    /// The `Buf`, advanced past what has been decoded
+    #[inline]
+    pub fn into_inner(self) -> B { self.0 }
+}
+
+#[cfg(feature = "bytes")]
+impl<B: bytes::Buf> Bytes for DecodeBuf<B> {
+    #[inline]
+    fn next_byte(&mut self) -> Option<u8> {
+        if self.0.has_remaining() { Some(self.0.get_u8()) } else { None }
+    }
+    #[inline]
+    fn peek_byte(&mut self) -> Option<u8> { self.0.chunk().first().cloned() }
+}
+
+#[cfg(feature = "bytes")]
+impl<B: bytes::Buf> Iterator for DecodeBuf<B> {
+    type Item = Result<char, InvalidSequence>;
+    #[inline]
+    fn next(&mut self) -> Option<Result<char, InvalidSequence>> { decode(self) }
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let l = self.0.remaining();
+        (l.div_ceil(4), Some(l))
+    }
+}
+
+#[cfg(feature = "bytes")]
+impl<B: bytes::Buf> iter::FusedIterator for DecodeBuf<B> {}
+
+/// An iterator over an iterator of UTF-16 code units of the characters they represent
+#[derive(Clone, Debug)]
+pub struct DecodeUtf16<I: Iterator<Item = u16>>(iter::Peekable<I>);
+
+/// Decodes an `Iterator` of code units as UTF-16.
+#[inline]
+pub fn decode_utf16<I: IntoIterator<Item = u16>>(i: I) -> DecodeUtf16<I::IntoIter> {
+    DecodeUtf16(i.into_iter().peekable())
+}
+
+impl<I: Iterator<Item = u16>> Iterator for DecodeUtf16<I> {
+    type Item = Result<char, InvalidSequence>;
+    fn next(&mut self) -> Option<Result<char, InvalidSequence>> {
+        self.0.next().map(|u| match u {
+            0xD800..=0xDBFF => match self.0.peek() {
+                Some(&v) if v & 0xFC00 == 0xDC00 => {
+                    self.0.next();
+                    Ok(unsafe { from_u32_unchecked(combine_surrogates(u as u32, v as u32)) })
+                },
+                Some(_) => Err(DecodeError::invalid(ErrorKind::Surrogate, 1)),
+                None => Err(DecodeError::incomplete(2, 1)),
+            },
+            0xDC00..=0xDFFF => Err(DecodeError::invalid(ErrorKind::Surrogate, 1)),
+            _ => Ok(unsafe { from_u32_unchecked(u as u32) }),
+        })
+    }
+}
+
+impl<I: iter::FusedIterator<Item = u16>> iter::FusedIterator for DecodeUtf16<I> {}
+
+/// An iterator over an iterator of bytes of the UTF-16 code units of the characters the
+/// bytes represent as UTF-8
+#[derive(Clone, Debug)]
+pub struct TranscodeUtf8ToUtf16<I: Iterator<Item = u8>> { iter: DecodeUtf8<I>, lo: Option<u16> }
+
+/// Transcodes an `Iterator` of bytes from UTF-8 to UTF-16, yielding each invalid
+/// sequence of the input in place of its code units.
+#[inline]
+pub fn transcode_utf8_to_utf16<I: IntoIterator<Item = u8>>(i: I)
+  -> TranscodeUtf8ToUtf16<I::IntoIter> {
+    TranscodeUtf8ToUtf16 { iter: decode_utf8(i), lo: None }
+}
+
+impl<I: Iterator<Item = u8>> Iterator for TranscodeUtf8ToUtf16<I> {
+    type Item = Result<u16, InvalidSequence>;
+    #[inline]
+    fn next(&mut self) -> Option<Result<u16, InvalidSequence>> {
+        if let Some(lo) = self.lo.take() { return Some(Ok(lo)) }
+        Some(self.iter.next()?.map(|c| match c as u32 {
+            x @ 0..=0xFFFF => x as u16,
+            x => {
+                let (hi, lo) = split_surrogates(x);
+                self.lo = Some(lo);
+                hi
+            },
+        }))
+    }
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        // Each item of `iter` is 1 or 2 code units.
+        let l = self.lo.is_some() as usize;
+        let (lo, hi) = self.iter.size_hint();
+        (lo.saturating_add(l), hi.and_then(|hi| hi.checked_mul(2)).and_then(|hi| hi.checked_add(l)))
+    }
+}
+
+impl<I: iter::FusedIterator<Item = u8>> iter::FusedIterator for TranscodeUtf8ToUtf16<I> {}
+
+/// An iterator over an iterator of UTF-16 code units of the bytes of the characters they
+/// represent in UTF-8
+#[derive(Clone, Debug)]
+pub struct TranscodeUtf16ToUtf8<I: Iterator<Item = u16>> {
+    iter: DecodeUtf16<I>,
+    buf: [u8; 4],
+    pos: u8,
+    end: u8,
+}
+
+/// Transcodes an `Iterator` of code units from UTF-16 to UTF-8, yielding each unpaired
+/// surrogate of the input in place of its bytes.
+#[inline]
+pub fn transcode_utf16_to_utf8<I: IntoIterator<Item = u16>>(i: I)
+  -> TranscodeUtf16ToUtf8<I::IntoIter> {
+    TranscodeUtf16ToUtf8 { iter: decode_utf16(i), buf: [0; 4], pos: 0, end: 0 }
+}
+
+impl<I: Iterator<Item = u16>> Iterator for TranscodeUtf16ToUtf8<I> {
+    type Item = Result<u8, InvalidSequence>;
+    #[inline]
+    fn next(&mut self) -> Option<Result<u8, InvalidSequence>> {
+        if self.pos == self.end {
+            let c = match self.iter.next()? { Ok(c) => c, Err(e) => return Some(Err(e)) };
+            self.pos = 0;
+            self.end = c.try_encode_utf8(&mut self.buf).map_or(0, |s| s.len()) as u8;
+        }
+        self.pos += 1;
+        Some(Ok(self.buf[self.pos as usize - 1]))
+    }
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        // Each code unit is 1 to 3 bytes, or 4 with its pair, or an error.
+        let l = (self.end - self.pos) as usize;
+        let (lo, hi) = self.iter.0.size_hint();
+        (lo.div_ceil(2).saturating_add(l),
+         hi.and_then(|hi| hi.checked_mul(3)).and_then(|hi| hi.checked_add(l)))
+    }
+}
+
+impl<I: iter::FusedIterator<Item = u16>> iter::FusedIterator for TranscodeUtf16ToUtf8<I> {}
+
+/// Code point of the surrogate pair of `hi` and `lo`, which must be high and low
+/// surrogates
+#[inline]
+fn combine_surrogates(hi: u32, lo: u32) -> u32 { 0x10000 + ((hi & 0x3FF) << 10 | lo & 0x3FF) }
+
+/// Surrogate pair of `x`, which must be a supplementary code point
+#[inline]
+fn split_surrogates(x: u32) -> (u16, u16) {
+    let x = x - 0x10000;
+    (0xD800 | (x >> 10) as u16, 0xDC00 | (x & 0x3FF) as u16)
+}
+
+/// The supplementary character which `high` and `low` represent in UTF-16, if they're a
+/// high and a low surrogate
+#[inline]
+pub fn decode_surrogate_pair(high: u16, low: u16) -> Option<char> {
+    if 0xD800 != high & 0xFC00 || 0xDC00 != low & 0xFC00 { return None }
+    Some(unsafe { from_u32_unchecked(combine_surrogates(high as u32, low as u32)) })
+}
+
+/// The surrogate pair which represents `c` in UTF-16, if it's a supplementary character
+#[inline]
+pub fn encode_surrogate_pair(c: char) -> Option<(u16, u16)> {
+    if (c as u32) < 0x10000 { return None }
+    Some(split_surrogates(c as u32))
+}
+
+/// A Unicode code point, which may be a surrogate, unlike a `char`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CodePoint(u32);
+
+impl CodePoint {
+    /// `x` as a code point, if it's at most `0x10FFFF`
+    #[inline]
+    pub const fn from_u32(x: u32) -> Option<Self> { if x > 0x10FFFF { None } else { Some(CodePoint(x)) } }
+
+    #[inline]
+    pub const fn from_char(c: char) -> Self { CodePoint(c as u32) }
+
+    #[inline]
+    pub const fn to_u32(self) -> u32 { self.0 }
+
+    /// The code point as a `char`, if it isn't a surrogate
+    #[inline]
+    pub const fn to_char(self) -> Option<char> { from_u32(self.0) }
+
+    #[inline]
+    pub const fn is_surrogate(self) -> bool { 0xD800 <= self.0 && self.0 <= 0xDFFF }
+}
+
+impl From<char> for CodePoint {
+    #[inline]
+    fn from(c: char) -> Self { CodePoint::from_char(c) }
+}
+
+/// An iterator over an iterator of UTF-32 code units of the characters they represent
+#[derive(Clone, Debug)]
+pub struct DecodeUtf32<I: Iterator<Item = u32>>(I);
+
+/// Decodes an `Iterator` of code units as UTF-32.
+#[inline]
+pub fn decode_utf32<I: IntoIterator<Item = u32>>(i: I) -> DecodeUtf32<I::IntoIter> {
+    DecodeUtf32(i.into_iter())
+}
+
+impl<I: Iterator<Item = u32>> Iterator for DecodeUtf32<I> {
+    type Item = Result<char, InvalidSequence>;
+    #[inline]
+    fn next(&mut self) -> Option<Result<char, InvalidSequence>> {
+        self.0.next().map(|x| from_u32(x).ok_or(DecodeError::invalid(match x {
+            0xD800..=0xDFFF => ErrorKind::Surrogate,
+            _ => ErrorKind::OutOfRange,
+        }, 1)))
+    }
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) { self.0.size_hint() }
+}
+
+impl<I: iter::FusedIterator<Item = u32>> iter::FusedIterator for DecodeUtf32<I> {}
+
+/// Order of the bytes of a code unit
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Endian {
+    /// Least significant byte first
+    Little,
+    /// Most significant byte first
+    Big,
+}
+
+/// Read a code unit of `n` bytes, or return how many bytes there are if fewer.
+#[inline]
+fn read_unit<I: Iterator<Item = u8>>(iter: &mut I, endian: Endian, n: usize)
+  -> Option<Result<u32, usize>> {
+    let mut x = 0;
+    for k in 0..n {
+        let b = match iter.next() {
+            Some(b) => b as u32,
+            None if 0 == k => return None,
+            None => return Some(Err(k)),
+        };
+        x = match endian { Endian::Little => x | b << (8 * k), Endian::Big => x << 8 | b };
+    }
+    Some(Ok(x))
+}
+
+/// An iterator over an iterator of bytes of the characters they represent as UTF-16
+#[derive(Clone, Debug)]
+pub struct DecodeUtf16Bytes<I: Iterator<Item = u8>> { iter: I, endian: Endian, next: Option<u16> }
+
+/// Decodes an `Iterator` of bytes as UTF-16 of the given byte order. The length of an
+/// invalid sequence is in bytes, and an odd byte at the end is an incomplete sequence.
+#[inline]
+pub fn decode_utf16_bytes<I: IntoIterator<Item = u8>>(i: I, endian: Endian)
+  -> DecodeUtf16Bytes<I::IntoIter> {
+    DecodeUtf16Bytes { iter: i.into_iter(), endian, next: None }
+}
+
+impl<I: Iterator<Item = u8>> Iterator for DecodeUtf16Bytes<I> {
+    type Item = Result<char, InvalidSequence>;
+    fn next(&mut self) -> Option<Result<char, InvalidSequence>> {
+        let (iter, endian) = (&mut self.iter, self.endian);
+        let u = match self.next.take().map(|u| Ok(u as u32)).or_else(|| read_unit(iter, endian, 2))? {
+            Ok(u) => u,
+            Err(got) => return Some(Err(DecodeError::incomplete(2, got))),
+        };
+        Some(match u {
+            0xD800..=0xDBFF => match read_unit(&mut self.iter, self.endian, 2) {
+                Some(Ok(v)) if v & 0xFC00 == 0xDC00 =>
+                    Ok(unsafe { from_u32_unchecked(combine_surrogates(u, v)) }),
+                Some(Ok(v)) => {
+                    self.next = Some(v as u16);
+                    Err(DecodeError::invalid(ErrorKind::Surrogate, 2))
+                },
+                Some(Err(got)) => Err(DecodeError::incomplete(4, 2 + got)),
+                None => Err(DecodeError::incomplete(4, 2)),
+            },
+            0xDC00..=0xDFFF => Err(DecodeError::invalid(ErrorKind::Surrogate, 2)),
+            _ => Ok(unsafe { from_u32_unchecked(u) }),
+        })
+    }
+}
+
+impl<I: iter::FusedIterator<Item = u8>> iter::FusedIterator for DecodeUtf16Bytes<I> {}
+
+/// An iterator over an iterator of bytes of the characters they represent as UTF-32
+#[derive(Clone, Debug)]
+pub struct DecodeUtf32Bytes<I: Iterator<Item = u8>> { iter: I, endian: Endian }
+
+/// Decodes an `Iterator` of bytes as UTF-32 of the given byte order. The length of an
+/// invalid sequence is in bytes, and 1 to 3 bytes at the end are an incomplete sequence.
+#[inline]
+pub fn decode_utf32_bytes<I: IntoIterator<Item = u8>>(i: I, endian: Endian)
+  -> DecodeUtf32Bytes<I::IntoIter> {
+    DecodeUtf32Bytes { iter: i.into_iter(), endian }
+}
+
+impl<I: Iterator<Item = u8>> Iterator for DecodeUtf32Bytes<I> {
+    type Item = Result<char, InvalidSequence>;
+    #[inline]
+    fn next(&mut self) -> Option<Result<char, InvalidSequence>> {
+        Some(match read_unit(&mut self.iter, self.endian, 4)? {
+            Ok(x) => from_u32(x).ok_or(DecodeError::invalid(match x {
+                0xD800..=0xDFFF => ErrorKind::Surrogate,
+                _ => ErrorKind::OutOfRange,
+            }, 4)),
+            Err(got) => Err(DecodeError::incomplete(4, got)),
+        })
+    }
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (lo, hi) = self.iter.size_hint();
+        (lo.div_ceil(4), hi.map(|hi| hi.div_ceil(4)))
+    }
+}
+
+impl<I: iter::FusedIterator<Item = u8>> iter::FusedIterator for DecodeUtf32Bytes<I> {}
+
+/// Whether `x` is a Unicode scalar value, i.e. a `char`: at most `0x10FFFF`, and not a
+/// surrogate code point
+#[inline]
+pub const fn is_valid_scalar(x: u32) -> bool { x <= 0x10FFFF && !(0xD800 <= x && x <= 0xDFFF) }
+
+mod private {
+    pub trait UtfExtSealed {}
+}
+use private::*;
+
+pub trait UtfExt: UtfExtSealed {
+    type UtfSlice: ?Sized;
+    /// Encode the character into the given buffer; return `None` if the buffer is too short.
+    fn try_encode_utf8(self, bs: &mut [u8]) -> Option<&mut Self::UtfSlice>;
+    /// Encode the character into the given buffer as `try_encode_utf8` does, and return also
+    /// the rest of the buffer, to encode more into.
+    fn try_encode_utf8_split(self, bs: &mut [u8]) -> Option<(&mut Self::UtfSlice, &mut [u8])>;
+    /// Encode the character into the given buffer as `try_encode_utf8` does, if it's a
+    /// Unicode scalar value, so the output is always valid UTF-8; return `None` if it isn't,
+    /// or if the buffer is too short.
+    fn try_encode_utf8_scalar(self, bs: &mut [u8]) -> Option<&mut Self::UtfSlice>;
+    /// Encode the character into an array, and return it with the number of bytes written.
+    fn to_utf8_array(self) -> ([u8; 4], usize);
+    /// Encode the character into the given buffer as `try_encode_utf8` does, but for U+0000,
+    /// which it writes as `C0 80`, so that no byte is 0; return `None` if the buffer is too
+    /// short. This is not valid UTF-8, so decode it with `decode_utf8_modified`.
+    fn try_encode_utf8_modified(self, bs: &mut [u8]) -> Option<&mut [u8]>;
+    /// Number of bytes `try_encode_utf8` needs to encode the character
+    fn encoded_len(self) -> usize;
+    /// Encode the character into the given buffer as UTF-16; return `None` if the buffer is
+    /// too short.
+    fn try_encode_utf16(self, out: &mut [u16]) -> Option<&mut [u16]>;
+    /// Encode the character into the given buffer, with no checks.
+    ///
+    /// # Safety
+    ///
+    /// The buffer must be at least `encoded_len` bytes long, and the character a Unicode
+    /// scalar value.
+    unsafe fn encode_utf8_unchecked(self, bs: &mut [u8]) -> &mut Self::UtfSlice;
+}
+
+impl UtfExtSealed for char {}
+impl UtfExtSealed for u32 {}
+
+impl UtfExt for char {
+    type UtfSlice = str;
+    #[inline]
+    fn try_encode_utf8(self, bs: &mut [u8]) -> Option<&mut str> {
+        self.try_encode_utf8_split(bs).map(|(s, _)| s)
+    }
+    #[inline]
+    fn try_encode_utf8_split(self, bs: &mut [u8]) -> Option<(&mut str, &mut [u8])> {
+        (self as u32).try_encode_utf8_split(bs)
+            .map(|(bs, rest)| (unsafe { str::from_utf8_unchecked_mut(bs) }, rest))
+    }
+    #[inline]
+    fn try_encode_utf8_scalar(self, bs: &mut [u8]) -> Option<&mut str> { self.try_encode_utf8(bs) }
+    #[inline]
+    fn to_utf8_array(self) -> ([u8; 4], usize) { (self as u32).to_utf8_array() }
+    #[inline]
+    fn try_encode_utf8_modified(self, bs: &mut [u8]) -> Option<&mut [u8]> {
+        (self as u32).try_encode_utf8_modified(bs)
+    }
+    #[inline]
+    fn encoded_len(self) -> usize { utf8_len(self as u32) }
+    #[inline]
+    fn try_encode_utf16(self, out: &mut [u16]) -> Option<&mut [u16]> {
+        (self as u32).try_encode_utf16(out)
+    }
+    #[inline]
+    unsafe fn encode_utf8_unchecked(self, bs: &mut [u8]) -> &mut str {
+        str::from_utf8_unchecked_mut((self as u32).encode_utf8_unchecked(bs))
+    }
+}
+
+impl UtfExt for u32 {
+    type UtfSlice = [u8];
+    /// Encodes also surrogate code points, which aren't valid UTF-8, as
+    /// `try_encode_utf8_scalar` doesn't; fails for values above `0x10FFFF`.
+    #[inline]
+    fn try_encode_utf8(self, bs: &mut [u8]) -> Option<&mut [u8]> {
+        self.try_encode_utf8_split(bs).map(|(bs, _)| bs)
+    }
+    fn try_encode_utf8_split(mut self, bs: &mut [u8]) -> Option<(&mut [u8], &mut [u8])> {
+        if self > 0x10FFFF { return None }
+        let l = utf8_len(self);
+        let first = !(!0u8 >> l);
+        if l > bs.len() { return None }
+        let (bs0, rest) = bs.split_at_mut(l);
+        {
+            let (b0, bs) = bs0.split_first_mut()?;
+            for b in bs.iter_mut().rev() {
+                *b = self as u8 & 0x3F | 0x80;
+                self >>= 6;
+            }
+            *b0 = self as u8 | if l > 1 { first } else { 0 };
+        }
+        Some((bs0, rest))
+    }
+    #[inline]
+    fn try_encode_utf8_scalar(self, bs: &mut [u8]) -> Option<&mut [u8]> {
+        if is_valid_scalar(self) { self.try_encode_utf8(bs) } else { None }
+    }
+    /// Values above `0x10FFFF` are not written, so the length is 0.
+    #[inline]
+    fn to_utf8_array(self) -> ([u8; 4], usize) {
+        let mut bs = [0; 4];
+        let l = self.try_encode_utf8(&mut bs).map_or(0, |bs| bs.len());
+        (bs, l)
+    }
+    fn try_encode_utf8_modified(self, bs: &mut [u8]) -> Option<&mut [u8]> {
+        if 0 != self { return self.try_encode_utf8(bs) }
+        let bs = bs.get_mut(..2)?;
+        bs.copy_from_slice(&[0xC0, 0x80]);
+        Some(bs)
+    }
+    /// Surrogate code points take 3 bytes like their neighbours. Values above
+    /// `0x10FFFF` can't be encoded, so take 0.
+    #[inline]
+    fn encoded_len(self) -> usize { if self > 0x10FFFF { 0 } else { utf8_len(self) } }
+    /// Fails also for surrogate code points and values above `0x10FFFF`, which UTF-16
+    /// can't represent.
+    fn try_encode_utf16(self, out: &mut [u16]) -> Option<&mut [u16]> {
+        match self {
+            0xD800..=0xDFFF | 0x110000..=0xFFFFFFFF => None,
+            0..=0xFFFF => {
+                let out = out.get_mut(0..1)?;
+                out[0] = self as u16;
+                Some(out)
+            },
+            _ => {
+                let out = out.get_mut(0..2)?;
+                let (hi, lo) = split_surrogates(self);
+                out[0] = hi;
+                out[1] = lo;
+                Some(out)
+            },
+        }
+    }
+    #[inline]
+    unsafe fn encode_utf8_unchecked(mut self, bs: &mut [u8]) -> &mut [u8] {
+        let l = utf8_len(self);
+        let bs = bs.get_unchecked_mut(..l);
+        for k in (1..l).rev() {
+            *bs.get_unchecked_mut(k) = self as u8 & 0x3F | 0x80;
+            self >>= 6;
+        }
+        *bs.get_unchecked_mut(0) = self as u8 | if l > 1 { !(!0u8 >> l) } else { 0 };
+        bs
+    }
+}
+
+/// An iterator over an iterator of characters of the bytes which represent them as UTF-8
+///
+/// The number of bytes is known only per character, so this isn't an
+/// `ExactSizeIterator`; `encoded_byte_len` counts them by summing `encoded_len`.
+#[derive(Clone, Debug)]
+pub struct EncodeUtf8<I: Iterator<Item = char>> { iter: I, buf: [u8; 4], pos: u8, end: u8 }
+
+/// Encodes an `Iterator` of characters as UTF-8.
+#[inline]
+pub fn encode_utf8<I: IntoIterator<Item = char>>(i: I) -> EncodeUtf8<I::IntoIter> {
+    EncodeUtf8 { iter: i.into_iter(), buf: [0; 4], pos: 0, end: 0 }
+}
+
+impl<I: Iterator<Item = char>> Iterator for EncodeUtf8<I> {
+    type Item = u8;
+    #[inline]
+    fn next(&mut self) -> Option<u8> {
+        if self.pos == self.end {
+            let c = self.iter.next()?;
+            self.pos = 0;
+            self.end = c.try_encode_utf8(&mut self.buf).map_or(0, |bs| bs.len()) as u8;
+        }
+        self.pos += 1;
+        Some(self.buf[self.pos as usize - 1])
+    }
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        // Each character takes 1 to 4 bytes.
+        let l = (self.end - self.pos) as usize;
+        let (lo, hi) = self.iter.size_hint();
+        (lo.saturating_add(l), hi.and_then(|hi| hi.checked_mul(4)).and_then(|hi| hi.checked_add(l)))
+    }
+}
+
+impl<I: iter::FusedIterator<Item = char>> iter::FusedIterator for EncodeUtf8<I> {}
+
+/// An iterator over an iterator of characters of each with its representation as UTF-8,
+/// and the number of bytes thereof
+#[derive(Clone, Debug)]
+pub struct EncodeUtf8Chunks<I: Iterator<Item = char>>(I);
+
+/// Encodes an `Iterator` of characters as UTF-8, a character at a time.
+#[inline]
+pub fn encode_utf8_chunks<I: IntoIterator<Item = char>>(i: I) -> EncodeUtf8Chunks<I::IntoIter> {
+    EncodeUtf8Chunks(i.into_iter())
+}
+
+impl<I: Iterator<Item = char>> Iterator for EncodeUtf8Chunks<I> {
+    type Item = (char, [u8; 4], usize);
+    #[inline]
+    fn next(&mut self) -> Option<(char, [u8; 4], usize)> {
+        let c = self.0.next()?;
+        let (bs, l) = c.to_utf8_array();
+        Some((c, bs, l))
+    }
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) { self.0.size_hint() }
+}
+
+impl<I: ExactSizeIterator<Item = char>> ExactSizeIterator for EncodeUtf8Chunks<I> {}
+
+impl<I: iter::FusedIterator<Item = char>> iter::FusedIterator for EncodeUtf8Chunks<I> {}
+
+impl<I: DoubleEndedIterator<Item = char>> DoubleEndedIterator for EncodeUtf8Chunks<I> {
+    #[inline]
+    fn next_back(&mut self) -> Option<(char, [u8; 4], usize)> {
+        let c = self.0.next_back()?;
+        let (bs, l) = c.to_utf8_array();
+        Some((c, bs, l))
+    }
+}
+
+/// Encode `chars` as UTF-8 into `out`; return the number of bytes written, or, if `out`
+/// is too short for all of them, `Err` of the number of bytes of the characters which fit,
+/// written in full.
+pub fn encode_into(chars: &[char], out: &mut [u8]) -> Result<usize, usize> {
+    let mut l = 0;
+    for &c in chars {
+        match c.try_encode_utf8(&mut out[l..]) {
+            Some(s) => l += s.len(),
+            None => return Err(l),
+        }
+    }
+    Ok(l)
+}
+
+/// Number of bytes `encode_into` needs to encode all of `chars`
+#[inline]
+pub fn encoded_len_slice(chars: &[char]) -> usize { chars.iter().map(|&c| c.encoded_len()).sum() }
+
+/// Number of bytes `encode_utf8` yields for the characters
+#[inline]
+pub fn encoded_byte_len<I: IntoIterator<Item = char>>(i: I) -> usize {
+    i.into_iter().map(UtfExt::encoded_len).sum()
+}
+
+/// Encode the characters as UTF-8 into the given writer.
+pub fn encode_to_fmt<W: fmt::Write, I: IntoIterator<Item = char>>(w: &mut W, chars: I)
+  -> fmt::Result {
+    let mut buf = [0; 4];
+    for c in chars { c.try_encode_utf8(&mut buf).map_or(Ok(()), |s| w.write_str(s))?; }
+    Ok(())
+}
+
+/// Encode the characters as UTF-8 into the given writer, buffering to write in bulk.
+#[cfg(feature = "std")]
+#[inline]
+pub fn encode_to_io<W: std::io::Write, I: IntoIterator<Item = char>>(w: &mut W, chars: I)
+  -> std::io::Result<()> {
+    encode_buffered(|bs| w.write_all(bs), chars)
+}
+
+/// Encode the characters as UTF-8 into the given `embedded_io::Write`r, buffering to write
+/// in bulk.
+#[cfg(feature = "embedded-io")]
+#[inline]
+pub fn encode_embedded<W: embedded_io::Write, I: IntoIterator<Item = char>>(w: &mut W, chars: I)
+  -> Result<(), W::Error> {
+    encode_buffered(|bs| w.write_all(bs), chars)
+}
+
+/// Encode the characters as UTF-8 onto the end of the given `bytes::BufMut`, which panics,
+/// as `BufMut::put_slice` does, if it runs out of room.
+#[cfg(feature = "bytes")]
+pub fn encode_to_buf<B: bytes::BufMut, I: IntoIterator<Item = char>>(buf: &mut B, chars: I) {
+    let mut bs = [0; 4];
+    for c in chars { buf.put_slice(c.try_encode_utf8(&mut bs).map_or(&[][..], |s| s.as_bytes())); }
+}
+
+/// Encode the characters as UTF-8 into a buffer, and pass it to `write_all` whenever it
+/// may not hold the next character, and at the end.
+#[cfg(any(feature = "std", feature = "embedded-io"))]
+fn encode_buffered<E, F, I>(mut write_all: F, chars: I) -> Result<(), E>
+  where F: FnMut(&[u8]) -> Result<(), E>, I: IntoIterator<Item = char> {
+    let mut buf = [0; 256];
+    let mut l = 0;
+    for c in chars {
+        if buf.len() - l < MAX_UTF8_LEN { write_all(&buf[..l])?; l = 0; }
+        l += c.try_encode_utf8(&mut buf[l..]).map_or(0, |s| s.len());
+    }
+    write_all(&buf[..l])
+}
+
+/// Number of bytes in the UTF-8 representation of a value with `lz` leading zeros, which
+/// is at most 32 for a `u32`: 1 to 6, as for the original UTF-8, or 0 above U+7FFFFFFF,
+/// which has none
+#[inline]
+const fn utf8_len_from_leading_zeros(lz: u32) -> u32 {
+    debug_assert!(lz <= 32);
+    match lz {
+        0 => 0,        // 32 bits
+        1..=5 => 6,    // 27 to 31 bits
+        6..=10 => 5,   // 22 to 26 bits
+        11..=15 => 4,  // 17 to 21 bits
+        16..=20 => 3,  // 12 to 16 bits
+        21..=24 => 2,  // 8 to 11 bits
+        _ => 1,        // 0 to 7 bits
+    }
+}
+
+#[inline]
+fn utf8_len(x: u32) -> usize { utf8_len_from_leading_zeros(x.leading_zeros()) as usize }
+
+/// `Err` of an invalid sequence, for tests
+#[cfg(test)]
+fn invalid<A>(kind: ErrorKind, len: usize) -> Result<A, DecodeError> {
+    Err(DecodeError::invalid(kind, len))
+}
+
+/// `Err` of an incomplete sequence, for tests
+#[cfg(test)]
+fn incomplete<A>(needed: usize, got: usize) -> Result<A, DecodeError> {
+    Err(DecodeError::incomplete(needed, got))
+}
+
+#[test]
+fn test_decode() {
+    use std::vec::Vec;
+    use std::iter::FromIterator;
+
+    for &(str, bs) in [("", &[] as &[u8]),
+                       ("A", &[0x41u8] as &[u8]),
+                       ("��", &[0xC1u8, 0x81u8] as &[u8]),
+                       ("♥", &[0xE2u8, 0x99u8, 0xA5u8]),
+                       ("♥A", &[0xE2u8, 0x99u8, 0xA5u8, 0x41u8] as &[u8]),
+                       ("�", &[0xE2u8, 0x99u8] as &[u8]),
+                       ("�A", &[0xE2u8, 0x99u8, 0x41u8] as &[u8]),
+                       ("�", &[0xC0u8] as &[u8]),
+                       ("�A", &[0xC0u8, 0x41u8] as &[u8]),
+                       ("�", &[0x80u8] as &[u8]),
+                       ("�A", &[0x80u8, 0x41u8] as &[u8]),
+                       ("�", &[0xFEu8] as &[u8]),
+                       ("�A", &[0xFEu8, 0x41u8] as &[u8]),
+                       ("�", &[0xFFu8] as &[u8]),
+                       ("�A", &[0xFFu8, 0x41u8] as &[u8])].iter() {
+        assert!(Iterator::eq(str.chars(),
+                             decode_utf8(bs.iter().cloned())
+                                 .map(|r_b| r_b.unwrap_or('\u{FFFD}'))),
+                "chars = {}, bytes = {:?}, decoded = {:?}", str, bs,
+                Vec::from_iter(decode_utf8(bs.iter().cloned())
+                                   .map(|r_b| r_b.unwrap_or('\u{FFFD}'))));
+        assert!(utf8_eq_lossy(bs, str), "chars = {}, bytes = {:?}", str, bs);
+        assert_eq!(validate(bs), utf8_eq(bs, str), "chars = {}, bytes = {:?}", str, bs);
+    }
+}
+
+#[test]
+fn test_encode() {
+    for &(s, x) in
+      [ ("A", 'A')
+      , ("♥", '♥')
+      ].iter() {
+        let mut buf = [0u8; MAX_UTF8_LEN];
+        let ts = x.try_encode_utf8(&mut buf[..]).map(|x| x as &str);
+        assert_eq!(Some(s), ts, "{:02X?}", ts.map(str::as_bytes));
+    }
+}
+
+#[test]
+fn test_decode_back() {
+    use std::vec::Vec;
+    use std::iter::FromIterator;
+
+    for &bs in [&[] as &[u8],
+                &[0x41u8],
+                &[0xE2u8, 0x99u8, 0xA5u8, 0x41u8],
+                &[0xC1u8, 0x81u8],
+                &[0xE2u8, 0x99u8],
+                &[0x41u8, 0xE2u8, 0x99u8],
+                &[0xE2u8, 0x99u8, 0xA5u8, 0x99u8],
+                &[0x41u8, 0x80u8, 0x80u8],
+                &[0xE2u8, 0x80u8, 0x80u8, 0x80u8, 0x80u8, 0x80u8, 0x80u8, 0x80u8, 0x80u8],
+                &[0x80u8, 0x80u8, 0x80u8, 0x80u8, 0x80u8, 0x80u8, 0x80u8, 0x80u8],
+                &[0xFCu8, 0x80u8, 0x80u8, 0x80u8, 0x80u8, 0x80u8, 0x80u8, 0x41u8],
+                &[0xF0u8, 0x9Fu8, 0x98u8, 0x80u8, 0xC0u8, 0xFFu8]].iter() {
+        let fwd = Vec::from_iter(decode_utf8(bs.iter().cloned()));
+        let mut back = Vec::from_iter(decode_utf8(bs.iter().cloned()).rev());
+        back.reverse();
+        assert_eq!(fwd, back, "bytes = {:02X?}", bs);
+
+        for n in 0..=fwd.len() {
+            let mut it = decode_utf8(bs.iter().cloned());
+            let mut xs = Vec::from_iter(it.by_ref().take(n));
+            let mut ys = Vec::from_iter(it.rev());
+            ys.reverse();
+            xs.append(&mut ys);
+            assert_eq!(fwd, xs, "bytes = {:02X?}, n = {}", bs, n);
+        }
+    }
+}
+
+#[test]
+fn test_decode_incomplete() {
+    use std::vec::Vec;
+    use std::iter::FromIterator;
+    use ErrorKind::*;
+
+    for &(bs, rs) in [(&[0xE2u8, 0x99u8] as &[u8], &[incomplete(3, 2)] as &[_]),
+                      (&[0xF0u8], &[incomplete(4, 1)]),
+                      (&[0x41u8, 0xC2u8], &[Ok('A'), incomplete(2, 1)]),
+                      (&[0xE2u8, 0x99u8, 0x41u8], &[invalid(TruncatedSequence, 2), Ok('A')]),
+                      (&[0xC2u8, 0xC2u8], &[invalid(TruncatedSequence, 1), incomplete(2, 1)]),
+                      (&[0x80u8], &[invalid(UnexpectedContinuation, 1)])].iter() {
+        assert_eq!(rs, &Vec::from_iter(decode_utf8(bs.iter().cloned()))[..], "bytes = {:02X?}", bs);
+    }
+}
+
+#[test]
+fn test_display() {
+    use std::string::ToString;
+    use ErrorKind::*;
+
+    assert_eq!("invalid UTF-8 byte sequence", DecodeError::invalid(InvalidLeadByte, 1).to_string());
+    assert_eq!("incomplete UTF-8 byte sequence: 2 of 3 bytes",
+               DecodeError::incomplete(3, 2).to_string());
+}
+
+#[test]
+fn test_decode_slice_continuation() {
+    for &bs in [&[0xE2u8, 0x41u8, 0x41u8] as &[u8],
+                &[0xE2u8, 0x99u8, 0x41u8],
+                &[0xC2u8, 0xC2u8, 0x80u8],
+                &[0x80u8],
+                &[0xBFu8, 0x80u8]].iter() {
+        assert_eq!(None, decode_slice(bs), "bytes = {:02X?}", bs);
+    }
+    assert_eq!(Some(('♥', NonZeroUsize::new(3).unwrap())), decode_slice(&[0xE2, 0x99, 0xA5, 0x41]));
+}
+
+#[test]
+fn test_decode_slice_overlong() {
+    for &bs in [&[0xC0u8, 0x80u8] as &[u8],
+                &[0xC1u8, 0x81u8],
+                &[0xE0u8, 0x80u8, 0x80u8],
+                &[0xE0u8, 0x9Fu8, 0xBFu8],
+                &[0xF0u8, 0x8Fu8, 0xBFu8, 0xBFu8],
+                &[0xF8u8, 0x88u8, 0x80u8, 0x80u8, 0x80u8],
+                &[0xFCu8, 0x84u8, 0x80u8, 0x80u8, 0x80u8, 0x80u8]].iter() {
+        assert_eq!(None, decode_slice_u32(bs), "bytes = {:02X?}", bs);
+        assert_eq!(Some(Err(1)), decode_utf8(bs.iter().cloned()).next().map(|r| r.map_err(|e| e.len())),
+                   "bytes = {:02X?}", bs);
+    }
+    for &(bs, x) in [(&[0xC2u8, 0x80u8] as &[u8], 0x80),
+                     (&[0xE0u8, 0xA0u8, 0x80u8], 0x800),
+                     (&[0xF0u8, 0x90u8, 0x80u8, 0x80u8], 0x10000)].iter() {
+        assert_eq!(Some((x, NonZeroUsize::new(bs.len()).unwrap())), decode_slice_u32(bs));
+    }
+}
+
+#[test]
+fn test_decode_slice_surrogate() {
+    // `ED 80..=9F` begins U+D000 to U+D7FF, and `ED A0..=BF` the surrogates.
+    for b1 in 0x80..=0xBF { for b2 in 0x80..=0xBF {
+        let bs = [0xED, b1, b2];
+        let x = 0xD000 | (b1 as u32 & 0x3F) << 6 | b2 as u32 & 0x3F;
+        let n = NonZeroUsize::new(3).unwrap();
+        let c = if b1 < 0xA0 { Some(from_u32(x).unwrap()) } else { None };
+        assert_eq!(Some((x, n)), decode_slice_u32(&bs), "bytes = {:02X?}", bs);
+        assert_eq!(c.map(|c| (c, n)), decode_slice(&bs), "bytes = {:02X?}", bs);
+        assert_eq!(c.map(|c| (c, 3)), decode_slice_const(&bs), "bytes = {:02X?}", bs);
+        assert_eq!(c.map(|c| (c, n)), decode_slice_back(&bs), "bytes = {:02X?}", bs);
+        assert_eq!(c.ok_or(ErrorKind::Surrogate), iter_slice(&bs).next().unwrap().map_err(|e| e.kind()),
+                   "bytes = {:02X?}", bs);
+    } }
+    assert_eq!(Some(('\u{D7FF}', 3)), decode_slice_const(b"\xED\x9F\xBF"));
+    assert_eq!(None, decode_slice_const(b"\xED\xA0\x80"));
+    assert_eq!(None, decode_slice_const(b"\xED\xBF\xBF"));
+    assert_eq!(Some(('\u{E000}', 3)), decode_slice_const(b"\xEE\x80\x80"));
+}
+
+#[test]
+fn test_decode_slice_back() {
+    for &(bs, r) in [(&[] as &[u8], None),
+                     (&[0x41u8], Some(('A', 1))),
+                     (&[0x41u8, 0xE2u8, 0x99u8, 0xA5u8], Some(('♥', 3))),
+                     (&[0xF0u8, 0x9Fu8, 0x98u8, 0x80u8], Some(('😀', 4))),
+                     (&[0xE2u8, 0x99u8], None),
+                     (&[0xE2u8, 0x99u8, 0xA5u8, 0x99u8], None),
+                     (&[0x80u8, 0x80u8, 0x80u8, 0x80u8], None),
+                     (&[0xC0u8, 0x80u8], None),
+                     (&[0xEDu8, 0xA0u8, 0x80u8], None),
+                     (&[0xF4u8, 0x90u8, 0x80u8, 0x80u8], None)].iter() {
+        assert_eq!(r.map(|(c, n)| (c, NonZeroUsize::new(n).unwrap())), decode_slice_back(bs),
+                   "bytes = {:02X?}", bs);
+    }
+}
+
+#[test]
+fn test_encoded_len() {
+    for &c in ['\0', 'A', '\u{7F}', '\u{80}', '\u{7FF}', '\u{800}', '♥', '\u{FFFF}',
+               '\u{10000}', '😀', '\u{10FFFF}'].iter() {
+        assert_eq!(c.len_utf8(), c.encoded_len(), "char = {:?}", c);
+    }
+    for &(x, l) in [(0xD800u32, 3), (0x10FFFF, 4), (0x110000, 0), (0x200000, 0), (0x7FFFFFFF, 0),
+                    (0x80000000, 0), (!0, 0)].iter() {
+        assert_eq!(l, x.encoded_len(), "x = {:X}", x);
+        let mut buf = [0u8; MAX_UTF8_LEN];
+        assert_eq!(l, x.try_encode_utf8(&mut buf[..]).map_or(0, |bs| bs.len()), "x = {:X}", x);
+    }
+}
+
+#[test]
+fn test_encode_utf16() {
+    for &c in ['\0', 'A', '♥', '\u{FFFF}', '\u{10000}', '😀', '\u{10FFFF}'].iter() {
+        let mut buf = [0u16; MAX_UTF16_LEN];
+        let mut std_buf = [0u16; MAX_UTF16_LEN];
+        assert_eq!(Some(&mut *c.encode_utf16(&mut std_buf)), c.try_encode_utf16(&mut buf),
+                   "char = {:?}", c);
+        assert_eq!(None, c.try_encode_utf16(&mut buf[..c.len_utf16() - 1]), "char = {:?}", c);
+    }
+    for &x in [0xD800u32, 0xDFFF, 0x110000, !0].iter() {
+        assert_eq!(None, x.try_encode_utf16(&mut [0; 2]), "x = {:X}", x);
+    }
+}
+
+#[test]
+fn test_decode_utf16() {
+    use std::vec::Vec;
+    use std::iter::FromIterator;
+    use ErrorKind::*;
+
+    for &(us, rs) in [(&[] as &[u16], &[] as &[_]),
+                      (&[0x41], &[Ok('A')]),
+                      (&[0x2665, 0xD83D, 0xDE00], &[Ok('♥'), Ok('😀')]),
+                      (&[0xDBFF, 0xDFFF], &[Ok('\u{10FFFF}')]),
+                      (&[0xD83D, 0x41], &[invalid(Surrogate, 1), Ok('A')]),
+                      (&[0xD83D, 0xD83D, 0xDE00], &[invalid(Surrogate, 1), Ok('😀')]),
+                      (&[0xDE00, 0x41], &[invalid(Surrogate, 1), Ok('A')]),
+                      (&[0x41, 0xD83D], &[Ok('A'), incomplete(2, 1)])].iter() {
+        assert_eq!(rs, &Vec::from_iter(decode_utf16(us.iter().cloned()))[..], "units = {:04X?}", us);
+    }
+}
+
+#[test]
+fn test_decode_utf32() {
+    use std::vec::Vec;
+    use std::iter::FromIterator;
+    use ErrorKind::*;
+
+    assert_eq!(&[Ok('A'), Ok('😀'), invalid(Surrogate, 1), Ok('\u{10FFFF}'), invalid(OutOfRange, 1),
+                 invalid(OutOfRange, 1)],
+               &Vec::from_iter(decode_utf32([0x41, 0x1F600, 0xD800, 0x10FFFF, 0x110000, !0]
+                                                .iter().cloned()))[..]);
+}
+
+#[test]
+fn test_decode_lossy() {
+    use std::string::String;
+    use std::iter::FromIterator;
+
+    for &bs in [&[] as &[u8],
+                b"A\xE2\x99\xA5",
+                b"\xE2\x99",
+                b"\xE2\x99A",
+                b"\xC0\x80",
+                b"\xE0\x80\x80",
+                b"\xE0\x9F\xBFA",
+                b"\xED\xA0\x80",
+                b"\xED\x9F\xBF",
+                b"\xF0\x8F\xBF\xBF",
+                b"\xF4\x90\x80\x80",
+                b"\xF4\x8F\xBF",
+                b"\xF5\x80\x80\x80",
+                b"\xF8\x88\x80\x80\x80",
+                b"\x80\x80\xBF",
+                b"\xF0\x9F\x98\xF0\x9F\x98\x80"].iter() {
+        let s = String::from_utf8_lossy(bs);
+        assert_eq!(s, String::from_iter(decode_utf8_lossy(bs.iter().cloned())), "bytes = {:02X?}", bs);
+        assert_eq!(String::from_iter(s.chars().rev()),
+                   String::from_iter(decode_utf8_lossy(bs.iter().cloned()).rev()), "bytes = {:02X?}", bs);
+        assert_eq!(s.replace('\u{FFFD}', "?"),
+                   String::from_iter(decode_utf8_lossy_with(bs.iter().cloned(), '?')), "bytes = {:02X?}", bs);
+        assert_eq!(s.replace('\u{FFFD}', "😀"),
+                   String::from_iter(decode_utf8_lossy_with(bs.iter().cloned(), '😀')), "bytes = {:02X?}", bs);
+        assert_eq!(s.replace('\u{FFFD}', ""),
+                   String::from_iter(decode_utf8_filter(bs.iter().cloned())), "bytes = {:02X?}", bs);
+        assert_eq!(String::from_iter(s.replace('\u{FFFD}', "").chars().rev()),
+                   String::from_iter(decode_utf8_filter(bs.iter().cloned()).rev()), "bytes = {:02X?}", bs);
+    }
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn test_decode_to_string() {
+    use ErrorKind::*;
+
+    assert_eq!(Ok("A♥".into()), decode_to_string(b"A\xE2\x99\xA5".iter().cloned()));
+    assert_eq!(invalid(TruncatedSequence, 2), decode_to_string(b"A\xE2\x99A\xFF".iter().cloned()));
+    assert_eq!(incomplete(3, 2),
+               decode_to_string(b"A\xE2\x99".iter().cloned()));
+    assert_eq!("A\u{FFFD}A\u{FFFD}", decode_to_string_lossy(b"A\xE2\x99A\xFF".iter().cloned()));
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn test_decode_utf8_lines() {
+    use std::vec::Vec;
+    use std::iter::FromIterator;
+    use ErrorKind::*;
+
+    for &s in ["", "\n", "A", "A\n", "A\r\nB", "A\n\nB\r\n", "A\rB\r", "\r\n\r\n♥😀\n"].iter() {
+        assert_eq!(Vec::from_iter(s.lines().map(|l| Ok(l.into()))),
+                   Vec::from_iter(decode_utf8_lines(s.bytes())), "str = {:?}", s);
+    }
+    let bs = b"A\r\n\xE2\x99\xFF\r\nB\n\xE2\x99";
+    assert_eq!(&[Ok("A".into()), invalid(TruncatedSequence, 2), Ok("B".into()), incomplete(3, 2)][..],
+               &Vec::from_iter(decode_utf8_lines(bs.iter().cloned()))[..]);
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn test_encode_to_vec() {
+    use std::vec;
+
+    let s = "A\u{7F}\u{80}♥\u{FFFF}😀\u{10FFFF}";
+    assert_eq!(s.as_bytes(), &encode_to_vec(s.chars())[..]);
+    assert_eq!(Ok(s.as_bytes()), encode_to_vec_u32(s.chars().map(|c| c as u32)).as_deref());
+    assert_eq!(Ok(&b"A\xED\xA0\x80B"[..]), encode_to_vec_u32(vec![0x41, 0xD800, 0x42]).as_deref());
+    assert_eq!(Err(2), encode_to_vec_u32(vec![0x41, 0xD800, 0x110000, 0x80000000, 0x42]));
+    assert_eq!(Err(0), encode_to_vec_u32(Some(0x80000000)));
+    assert!(encode_to_vec(None).is_empty());
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn test_decode_utf8_lossy_cow() {
+    use std::string::String;
+
+    for &bs in [&b""[..], b"A\xE2\x99\xA5", b"A\xE2\x99A\x80\xF0\x9F\x98",
+                b"\xC0\x80\xED\xA0\x80\xF4\x90", b"\xE0\x80A\xF0\x9F\x98\x80\xE2\xFF"].iter() {
+        let s = decode_utf8_lossy_cow(bs);
+        assert_eq!(String::from_utf8_lossy(bs), s, "bytes = {:02X?}", bs);
+        assert_eq!(validate(bs), match s { Cow::Borrowed(_) => true, Cow::Owned(_) => false });
+    }
+}
+
+#[test]
+fn test_stream_decoder() {
+    use std::vec::Vec;
+    use std::iter::FromIterator;
+
+    let mut d = StreamDecoder::new();
+    assert_eq!(None, d.feed(&[0xE2, 0x99]).next());
+    assert_eq!(&[Ok('♥'), Ok('A')], &Vec::from_iter(d.feed(&[0xA5, 0x41]))[..]);
+    assert_eq!(None, d.finish());
+
+    let bs = b"A\xE2\x99\xA5\xF0\x9F\x98\x80\xE2\x99A\xF0\x9F\xC0\x80\xE2";
+    let rs = Vec::from_iter(decode_utf8(bs.iter().cloned()));
+    for n in 1..bs.len() {
+        let mut d = StreamDecoder::new();
+        let mut xs = Vec::new();
+        for chunk in bs.chunks(n) { xs.extend(d.feed(chunk)); }
+        xs.extend(d.finish().map(Err));
+        assert_eq!(rs, xs, "n = {}", n);
+
+        // Dropping each `Feed` after one item, and feeding the rest again, loses nothing.
+        let mut d = StreamDecoder::new();
+        let mut xs = Vec::new();
+        for mut chunk in bs.chunks(n) {
+            loop {
+                let mut feed = d.feed(chunk);
+                let r = feed.next();
+                chunk = &chunk[feed.consumed()..];
+                match r { Some(r) => xs.push(r), None => break }
+            }
+            assert!(chunk.is_empty());
+        }
+        xs.extend(d.finish().map(Err));
+        assert_eq!(rs, xs, "n = {}", n);
+    }
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_decode_reader() {
+    use std::{io, vec::Vec};
+    use std::iter::FromIterator;
+
+    struct Trickle<'a>(&'a [u8]);
+    impl<'a> io::Read for Trickle<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.split_first() {
+                Some((&b, bs)) => { buf[0] = b; self.0 = bs; Ok(1) },
+                None => Err(io::ErrorKind::BrokenPipe.into()),
+            }
+        }
+    }
+
+    let bs = b"A\xE2\x99\xA5\xE2\x99A\xF0\x9F\x98\x80\xE2";
+    let rs = Vec::from_iter(decode_reader(&bs[..]).map(Result::unwrap));
+    assert_eq!(Vec::from_iter(decode_utf8(bs.iter().cloned())), rs);
+    let mut it = decode_reader(Trickle(&bs[..4]));
+    assert_eq!(Some(Ok('A')), it.next().map(Result::unwrap));
+    assert_eq!(Some(Ok('♥')), it.next().map(Result::unwrap));
+    assert_eq!(Some(io::ErrorKind::BrokenPipe), it.next().map(|r| r.unwrap_err().kind()));
+}
+
+#[cfg(feature = "embedded-io")]
+#[test]
+fn test_decode_embedded() {
+    use std::vec::Vec;
+    use std::iter::FromIterator;
+
+    struct Trickle<'a>(&'a [u8]);
+    impl<'a> embedded_io::ErrorType for Trickle<'a> { type Error = embedded_io::ErrorKind; }
+    impl<'a> embedded_io::Read for Trickle<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, embedded_io::ErrorKind> {
+            match self.0.split_first() {
+                Some((&b, bs)) => { buf[0] = b; self.0 = bs; Ok(1) },
+                None => Err(embedded_io::ErrorKind::BrokenPipe),
+            }
+        }
+    }
+
+    let bs = b"A\xE2\x99\xA5\xE2\x99A\xF0\x9F\x98\x80\xE2";
+    let rs = Vec::from_iter(decode_embedded(&bs[..]).map(|r| r.map_err(|e| match e {
+        EmbeddedError::Decode(e) => e,
+        EmbeddedError::Io(e) => match e {},
+    })));
+    assert_eq!(Vec::from_iter(decode_utf8(bs.iter().cloned())), rs);
+    let mut it = decode_embedded(Trickle(&bs[..4]));
+    assert_eq!(Some(Ok('A')), it.next());
+    assert_eq!(Some(Ok('♥')), it.next());
+    assert_eq!(Some(Err(EmbeddedError::Io(embedded_io::ErrorKind::BrokenPipe))), it.next());
+}
+
+#[cfg(feature = "embedded-io")]
+#[test]
+fn test_encode_embedded() {
+    let s = "A♥😀".repeat(100);
+    let mut buf = [0; 1024];
+    let mut w = &mut buf[..];
+    encode_embedded(&mut w, s.chars()).unwrap();
+    let l = 1024 - w.len();
+    assert_eq!(s.as_bytes(), &buf[..l]);
+    let mut w = &mut buf[..4];
+    assert_eq!(Err(embedded_io::SliceWriteError::Full), encode_embedded(&mut w, s.chars()));
+}
+
+#[cfg(feature = "bytes")]
+#[test]
+fn test_buf() {
+    use std::vec::Vec;
+    use std::iter::FromIterator;
+    use bytes::Buf;
+
+    let bs = b"A\xE2\x99\xA5\xE2\x99A\xF0\x9F\x98\x80\xE2";
+    // Split across chunks, so that lookahead must cross between them
+    let buf = (&bs[..2]).chain(&bs[2..5]).chain(&bs[5..]);
+    assert_eq!(Vec::from_iter(decode_utf8(bs.iter().cloned())), Vec::from_iter(decode_buf(buf)));
+    let mut it = decode_buf(&bs[..]);
+    it.next();
+    it.next();
+    assert_eq!(&bs[4..], it.into_inner());
+
+    let mut v = Vec::new();
+    encode_to_buf(&mut v, "A♥😀".chars());
+    assert_eq!("A♥😀".as_bytes(), &v[..]);
+}
+
+#[cfg(feature = "heapless")]
+#[test]
+fn test_heapless() {
+    assert_eq!(Ok("A♥😀"), encode_to_heapless::<8, _>("A♥😀".chars()).as_ref().map(|s| &s[..]));
+    assert_eq!(Err(CapacityError), encode_to_heapless::<7, _>("A♥😀".chars()));
+    assert_eq!(Ok("A♥😀"), decode_to_heapless::<8, _>("A♥😀".bytes()).as_ref().map(|s| &s[..]));
+    assert_eq!(Err(HeaplessDecodeError::Capacity(CapacityError)), decode_to_heapless::<3, _>("A♥".bytes()));
+    assert_eq!(Err(HeaplessDecodeError::Decode(DecodeError::invalid(ErrorKind::InvalidLeadByte, 1))),
+               decode_to_heapless::<8, _>(b"A\xFF".iter().cloned()));
+}
+
+#[test]
+fn test_encode_to_fmt() {
+    use std::string::String;
+
+    let mut s = String::new();
+    encode_to_fmt(&mut s, "A♥😀".chars()).unwrap();
+    assert_eq!("A♥😀", s);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_encode_to_io() {
+    use std::{io, string::String, vec::Vec};
+    use std::iter::FromIterator;
+
+    struct Writes(Vec<u8>, usize);
+    impl io::Write for Writes {
+        fn write(&mut self, bs: &[u8]) -> io::Result<usize> {
+            self.0.extend_from_slice(bs);
+            self.1 += 1;
+            Ok(bs.len())
+        }
+        fn flush(&mut self) -> io::Result<()> { Ok(()) }
+    }
+
+    let s = String::from_iter("A♥😀".chars().cycle().take(300));
+    let mut w = Writes(Vec::new(), 0);
+    encode_to_io(&mut w, s.chars()).unwrap();
+    assert_eq!(s.as_bytes(), &w.0[..]);
+    assert!(w.1 <= 4, "writes = {}", w.1);
+}
+
+#[cfg(all(feature = "serde", feature = "alloc"))]
+#[test]
+fn test_deserialize_utf8_lossy() {
+    use serde::de::value::{BytesDeserializer, Error, SeqDeserializer};
+
+    assert_eq!(Ok("A♥\u{FFFD}".into()),
+               deserialize_utf8_lossy(BytesDeserializer::<Error>::new(b"A\xE2\x99\xA5\xFF")));
+    assert_eq!(Ok("A\u{FFFD}".into()),
+               deserialize_utf8_lossy(SeqDeserializer::<_, Error>::new(b"A\xE2\x99".iter().cloned())));
+}
+
+#[test]
+fn test_fused() {
+    fn assert_fused<I: iter::FusedIterator>(mut i: I) {
+        while i.next().is_some() {}
+        for _ in 0..3 { assert!(i.next().is_none()); }
+    }
+
+    assert_fused(decode_utf8(b"A\xE2\x99".iter().cloned()));
+    assert_fused(decode_utf8_lossy(b"A\xE2\x99".iter().cloned()));
+    assert_fused(decode_utf16([0x41, 0xD83D].iter().cloned()));
+    assert_fused(decode_utf32([0x41, 0xD83D].iter().cloned()));
+}
+
+#[test]
+fn test_size_hint() {
+    let mut it = decode_utf8(b"A\xE2\x99\xA5\xF0\x9F\x98\x80A".iter().cloned());
+    assert_eq!((3, Some(9)), it.size_hint());
+    assert_eq!(Some(Ok('A')), it.next());
+    assert_eq!(Some(0xE2), it.peek_byte());
+    assert_eq!((2, Some(8)), it.size_hint());
+    assert_eq!(Some(Ok('A')), it.next_back());
+    assert_eq!((2, Some(7)), it.size_hint());
+    assert_eq!(None, decode_utf8(iter::repeat(0x41)).size_hint().1);
+}
+
+#[test]
+fn test_char_indices() {
+    use std::vec::Vec;
+    use std::iter::FromIterator;
+    use ErrorKind::*;
+
+    let s = "A♥😀\u{7FF}";
+    assert_eq!(Vec::from_iter(s.char_indices().map(|(i, c)| (i, Ok(c)))),
+               Vec::from_iter(char_indices(s.as_bytes())));
+    assert_eq!(&[(0, Ok('A')), (1, invalid(TruncatedSequence, 2)), (3, Ok('A')),
+                 (4, invalid(UnexpectedContinuation, 1)),
+                 (5, incomplete(4, 3))],
+               &Vec::from_iter(char_indices(b"A\xE2\x99A\x80\xF0\x9F\x98"))[..]);
+    for &bs in [s.as_bytes(), b"A\xE2\x99A\x80\xF0\x9F\x98", b"\xC0\x80\xED\xA0\x80\xF4\x90A"].iter() {
+        assert_eq!(Vec::from_iter(char_indices(bs)), Vec::from_iter(decode_utf8_offsets(bs.iter().cloned())),
+                   "bytes = {:02X?}", bs);
+    }
+}
+
+#[test]
+fn test_count_chars() {
+    use ErrorKind::*;
+
+    for &s in ["", "A", "A♥😀\u{7FF}", "♥♥♥"].iter() {
+        assert_eq!(s.chars().count(), count_chars(s.as_bytes()), "str = {:?}", s);
+        assert_eq!(Ok(s.chars().count()), try_count_chars(s.as_bytes()), "str = {:?}", s);
+    }
+    assert_eq!(invalid(TruncatedSequence, 2), try_count_chars(b"A\xE2\x99A"));
+    assert_eq!(incomplete(3, 2), try_count_chars(b"A\xE2\x99"));
+}
+
+#[test]
+fn test_validate() {
+    for &bs in [&[] as &[u8],
+                b"A\xE2\x99\xA5\xF0\x9F\x98\x80",
+                b"A\xE2\x99",
+                b"A\xE2\x99A",
+                b"AA\xC0\x80",
+                b"\xED\x9F\xBF\xED\xA0\x80",
+                b"\xF4\x8F\xBF\xBF\xF4\x90\x80\x80",
+                b"\xE0\x9F\xBF",
+                b"\x80",
+                b"A\xFF"].iter() {
+        let n = str::from_utf8(bs).map_or_else(|e| e.valid_up_to(), str::len);
+        assert_eq!(n, validate_up_to(bs), "bytes = {:02X?}", bs);
+        assert_eq!(n == bs.len(), validate(bs), "bytes = {:02X?}", bs);
+    }
+}
+
+#[test]
+fn test_from_utf8() {
+    for &bs in [&[] as &[u8],
+                b"A\xE2\x99\xA5\xF0\x9F\x98\x80",
+                b"A\xE2\x99",
+                b"A\xE2\x99A",
+                b"AA\xC0\x80",
+                b"\xED\xA0\x80",
+                b"\xF4\x90\x80\x80",
+                b"\xF0\x9F\x98",
+                b"A\xFF"].iter() {
+        assert_eq!(str::from_utf8(bs).map_err(|e| (e.valid_up_to(), e.error_len())),
+                   from_utf8(bs).map_err(|e| (e.valid_up_to(), e.error_len())),
+                   "bytes = {:02X?}", bs);
+    }
+}
+
+#[test]
+fn test_bom() {
+    for &(bs, r) in [(&[] as &[u8], None),
+                     (b"\xEF\xBB\xBFA", Some((Bom::Utf8, 3))),
+                     (b"\xEF\xBB", None),
+                     (b"\xFF\xFEA\x00", Some((Bom::Utf16Le, 2))),
+                     (b"\xFE\xFF\x00A", Some((Bom::Utf16Be, 2))),
+                     (b"\xFF\xFE\x00\x00", Some((Bom::Utf32Le, 4))),
+                     (b"\xFF\xFE\x00", Some((Bom::Utf16Le, 2))),
+                     (b"\x00\x00\xFE\xFF", Some((Bom::Utf32Be, 4)))].iter() {
+        assert_eq!(r, detect_bom(bs), "bytes = {:02X?}", bs);
+    }
+    assert_eq!(b"A", strip_bom(b"\xEF\xBB\xBFA"));
+    assert_eq!(b"\xFF\xFEA\x00", strip_bom(b"\xFF\xFEA\x00"));
+}
+
+#[test]
+fn test_next_char() {
+    use ErrorKind::*;
+
+    assert_eq!(None, next_char(b""));
+    assert_eq!(Some((Ok('♥'), &b"A"[..])), next_char(b"\xE2\x99\xA5A"));
+    assert_eq!(Some((invalid(TruncatedSequence, 2), &b"A"[..])), next_char(b"\xE2\x99A"));
+    assert_eq!(Some((invalid(Overlong, 1), &b"\x80A"[..])), next_char(b"\xE0\x80A"));
+    assert_eq!(Some((incomplete(3, 2), &b""[..])), next_char(b"\xE2\x99"));
+}
+
+#[test]
+fn test_is_char_boundary() {
+    let s = "A♥😀";
+    for i in 0..s.len() + 3 {
+        assert_eq!(s.is_char_boundary(i), is_char_boundary(s.as_bytes(), i), "index = {}", i);
+    }
+    assert!(is_char_boundary(b"", 0));
+    assert!(is_char_boundary(b"\x80\x80", 0));
+    assert!(!is_char_boundary(b"\x80\x80", 1));
+    assert!(is_char_boundary(b"\x80\x80", 2));
+}
+
+#[test]
+fn test_floor_ceil_char_boundary() {
+    let bs = "A♥😀".as_bytes();
+    for &(i, floor, ceil) in [(0, 0, 0), (1, 1, 1), (2, 1, 4), (3, 1, 4), (4, 4, 4), (5, 4, 8),
+                              (8, 8, 8), (9, 8, 8), (!0, 8, 8)].iter() {
+        assert_eq!(floor, floor_char_boundary(bs, i), "index = {}", i);
+        assert_eq!(ceil, ceil_char_boundary(bs, i), "index = {}", i);
+    }
+    assert_eq!(0, floor_char_boundary(b"\x80\x80", 1));
+    assert_eq!(2, ceil_char_boundary(b"\x80\x80", 1));
+}
+
+#[test]
+fn test_truncate_to_char_boundary() {
+    let bs = "A♥😀".as_bytes();
+    for &(n, l) in [(0, 0), (1, 1), (3, 1), (4, 4), (7, 4), (8, 8), (100, 8)].iter() {
+        assert_eq!(&bs[..l], truncate_to_char_boundary(bs, n), "max_len = {}", n);
+    }
+}
+
+#[test]
+fn test_encode_utf8_unchecked() {
+    for &c in ['\0', 'A', '\u{7FF}', '\u{800}', '♥', '\u{FFFF}', '\u{10000}', '😀', '\u{10FFFF}'].iter() {
+        let mut buf = [0u8; 4];
+        let mut std_buf = [0u8; 4];
+        assert_eq!(c.encode_utf8(&mut std_buf), unsafe { c.encode_utf8_unchecked(&mut buf) },
+                   "char = {:?}", c);
+    }
+}
+
+#[test]
+fn test_decode_slice_const() {
+    const HEART: Option<(char, usize)> = decode_slice_const(b"\xE2\x99\xA5A");
+    const SURROGATE: Option<(u32, usize)> = decode_slice_u32_const(b"\xED\xA0\x80");
+    assert_eq!(Some(('♥', 3)), HEART);
+    assert_eq!(Some((0xD800, 3)), SURROGATE);
+    assert_eq!(None, decode_slice_const(b"\xED\xA0\x80"));
+    for &bs in [&[] as &[u8], b"A", b"\xC2\x80", b"\xC0\x80", b"\xE2\x99", b"\xE2\x41\x41",
+                b"\xF0\x9F\x98\x80", b"\xF4\x90\x80\x80", b"\xF8\x88\x80\x80\x80", b"\x80"].iter() {
+        assert_eq!(decode_slice(bs).map(|(c, l)| (c, l.get())), decode_slice_const(bs),
+                   "bytes = {:02X?}", bs);
+    }
+}
+
+#[test]
+fn test_dfa() {
+    // The automaton must agree with the rules of `decode_u32` everywhere.
+    fn check(bs: &[u8]) {
+        let (mut xs, mut ys) = (bs, bs);
+        assert_eq!(decode_u32(&mut xs, false), decode(&mut ys).map(|r| r.map(|c| c as u32)),
+                   "bytes = {:02X?}", bs);
+        assert_eq!(xs, ys, "bytes = {:02X?}", bs);
+        let mut rest = bs;
+        let mut n = 0;
+        let r = loop {
+            let l = rest.len();
+            match decode_u32(&mut rest, false) {
+                Some(Ok(_)) => n += l - rest.len(),
+                Some(Err(e)) => break Some((n, l - rest.len(), e)),
+                None => break None,
+            }
+        };
+        assert_eq!(r, first_invalid(bs), "bytes = {:02X?}", bs);
+    }
+
+    for b0 in 0..=0xFF {
+        check(&[b0]);
+        for b1 in 0..=0xFF {
+            check(&[b0, b1]);
+            for &b2 in [0x00, 0x7F, 0x80, 0x8F, 0x90, 0x9F, 0xA0, 0xBF, 0xC0, 0xC2, 0xE0, 0xFF].iter() {
+                check(&[b0, b1, b2]);
+                for &b3 in [0x41, 0x80, 0xBF, 0xC0].iter() { check(&[b0, b1, b2, b3]); }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_ascii_fast_path() {
+    use std::vec::Vec;
+
+    // Put non-ASCII and invalid bytes at every offset of the first few chunks of ASCII.
+    for &c in [&[0xC3, 0xA9][..], &[0xE2, 0x82, 0xAC], &[0xF0, 0x9F, 0x98, 0x80], &[0x80],
+               &[0xE2, 0x82], &[0xED, 0xA0, 0x80]].iter() {
+        for k in 0..48 {
+            for n in k..70 {
+                let mut bs = Vec::new();
+                bs.extend((0..k).map(|i| b'a' + i as u8 % 26));
+                bs.extend_from_slice(c);
+                bs.extend((k..n).map(|i| b'a' + i as u8 % 26));
+                let e = str::from_utf8(&bs).err();
+                assert_eq!(e.is_none(), validate(&bs), "bytes = {:02X?}", bs);
+                assert_eq!(e.map_or(bs.len(), |e| e.valid_up_to()), validate_up_to(&bs),
+                           "bytes = {:02X?}", bs);
+                assert_eq!(bs.iter().filter(|&&b| b & 0xC0 != 0x80).count(), count_chars(&bs),
+                           "bytes = {:02X?}", bs);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_error_len() {
+    use ErrorKind::*;
+
+    // The lengths of the items of `decode_utf8` must add up to the length of the input.
+    for &bs in [&b"A\xE2\x99A\x80\xF0\x9F\x98"[..],
+                b"\xC0\x80\xE0\x9F\xBF\xED\xA0\x80\xF4\x90\x80\x80\xF5",
+                b"\xF0\x9F\x98\xF0\x9F\x98\x80\xE2\x99",
+                b"\xE2\x99\xA5\x80\x80\xC2"].iter() {
+        let len = |r: Result<char, DecodeError>| r.map_or_else(|e| e.len(), char::len_utf8);
+        let n: usize = decode_utf8(bs.iter().cloned()).map(len).sum();
+        assert_eq!(bs.len(), n, "bytes = {:02X?}", bs);
+        let n: usize = decode_utf8(bs.iter().cloned()).rev().map(len).sum();
+        assert_eq!(bs.len(), n, "bytes = {:02X?}", bs);
+        assert_eq!(first_invalid(bs).map(|(_, l, _)| l), first_invalid(bs).map(|(_, _, e)| e.len()),
+                   "bytes = {:02X?}", bs);
+    }
+    assert_eq!(2, DecodeError::invalid(Overlong, 2).len());
+    assert_eq!(3, DecodeError::incomplete(4, 3).len());
+}
+
+#[test]
+fn test_decode_utf8_u32() {
+    use std::vec::Vec;
+    use std::iter::FromIterator;
+    use ErrorKind::*;
+
+    let e = |kind| invalid(kind, 1);
+    assert_eq!(&[Ok(0x41), Ok(0xD800), Ok(0xDFFF), Ok(0x1F600), e(Overlong), e(UnexpectedContinuation),
+                 e(OutOfRange), e(UnexpectedContinuation), incomplete(3, 2)],
+               &Vec::from_iter(decode_utf8_u32(b"A\xED\xA0\x80\xED\xBF\xBF\xF0\x9F\x98\x80\xE0\x80\xF4\x90\xED\xA0"
+                                                   .iter().cloned()))[..]);
+    for x in (0..0x110000).step_by(0x3F) {
+        let mut buf = [0; 4];
+        let bs = x.try_encode_utf8(&mut buf).unwrap();
+        assert_eq!(&[Ok(x)], &Vec::from_iter(decode_utf8_u32(bs.iter().cloned()))[..], "bytes = {:02X?}", bs);
+    }
+}
+
+#[test]
+fn test_encode_utf8() {
+    use std::vec::Vec;
+    use std::iter::FromIterator;
+
+    for &s in ["", "A", "A♥😀\u{7FF}\u{FFFF}\u{10000}\u{10FFFF}"].iter() {
+        assert_eq!(s.as_bytes(), &Vec::from_iter(encode_utf8(s.chars()))[..], "str = {:?}", s);
+        assert_eq!(s.len(), encoded_byte_len(s.chars()), "str = {:?}", s);
+    }
+    let mut it = encode_utf8(['A', '♥'].iter().cloned());
+    assert_eq!((2, Some(8)), it.size_hint());
+    it.next();
+    it.next();
+    assert_eq!((2, Some(2)), it.size_hint());
+}
+
+#[test]
+fn test_peek_char() {
+    use ErrorKind::*;
+
+    let bs = b"A\xE2\x99\xA5\xE2\x99A";
+    let mut it = decode_utf8(bs.iter().cloned());
+    assert_eq!(Some(&Ok('A')), it.peek_char());
+    assert_eq!(Some(&Ok('A')), it.peek_char());
+    assert_eq!((3, Some(7)), it.size_hint());
+    assert_eq!(Some(Ok('A')), it.next());
+    assert_eq!(Some(&Ok('♥')), it.peek_char());
+    assert_eq!(Some(Ok('♥')), it.next());
+    assert_eq!(Some(&invalid(TruncatedSequence, 2)), it.peek_char());
+    assert_eq!(Some(Ok('A')), it.next_back());
+    assert_eq!(Some(invalid(TruncatedSequence, 2)), it.next_back());
+    assert_eq!(None, it.peek_char());
+    assert_eq!(None, it.next());
+
+    let mut it = decode_utf8(bs.iter().cloned());
+    assert_eq!(Some(&Ok('A')), it.peek_char());
+    assert_eq!(Some(Ok('A')), it.next_back());
+    assert_eq!(Some(invalid(TruncatedSequence, 2)), it.next_back());
+    assert_eq!(Some(Ok('♥')), it.next_back());
+    assert_eq!(Some(Ok('A')), it.next_back());
+    assert_eq!(None, it.next());
+}
+
+#[test]
+fn test_decode_utf8_limited() {
+    use std::vec::Vec;
+    use std::iter::FromIterator;
+
+    let bs = b"A\xE2\x99\xA5A";
+    for &(n, rs) in [(0, &[] as &[_]),
+                     (1, &[Ok('A')]),
+                     (2, &[Ok('A'), incomplete(3, 1)]),
+                     (3, &[Ok('A'), incomplete(3, 2)]),
+                     (4, &[Ok('A'), Ok('♥')]),
+                     (9, &[Ok('A'), Ok('♥'), Ok('A')])].iter() {
+        let mut it = bs.iter().cloned();
+        assert_eq!(rs, &Vec::from_iter(decode_utf8_limited(it.by_ref(), n))[..], "limit = {}", n);
+        assert_eq!(bs.len() - Ord::min(n, bs.len()), it.count(), "limit = {}", n);
+    }
+}
+
+#[test]
+fn test_transcode_utf8_to_utf16() {
+    use std::vec::Vec;
+    use std::iter::FromIterator;
+    use ErrorKind::*;
+
+    let s = "A♥😀\u{FFFF}\u{10000}\u{10FFFF}";
+    assert_eq!(Vec::from_iter(s.encode_utf16().map(Ok)), Vec::from_iter(transcode_utf8_to_utf16(s.bytes())));
+    assert_eq!(&[Ok(0x41), invalid(TruncatedSequence, 2), Ok(0xD83D), Ok(0xDE00),
+                 incomplete(4, 1)],
+               &Vec::from_iter(transcode_utf8_to_utf16(b"A\xE2\x99\xF0\x9F\x98\x80\xF0".iter().cloned()))[..]);
+}
+
+#[test]
+fn test_transcode_utf16_to_utf8() {
+    use std::vec::Vec;
+    use std::iter::FromIterator;
+    use ErrorKind::*;
+
+    let s = "A♥😀\u{FFFF}\u{10000}\u{10FFFF}";
+    assert_eq!(Vec::from_iter(s.bytes().map(Ok)), Vec::from_iter(transcode_utf16_to_utf8(s.encode_utf16())));
+    assert_eq!(&[Ok(0x41), invalid(Surrogate, 1), Ok(0xF0), Ok(0x9F), Ok(0x98), Ok(0x80),
+                 invalid(Surrogate, 1), incomplete(2, 1)],
+               &Vec::from_iter(transcode_utf16_to_utf8([0x41, 0xD83D, 0xD83D, 0xDE00, 0xDE00, 0xD83D]
+                                                            .iter().cloned()))[..]);
+}
+
+#[test]
+fn test_error_kind() {
+    use ErrorKind::*;
+
+    for &(bs, kind, len) in [(&[0x80u8, 0x41] as &[u8], UnexpectedContinuation, 1),
+                             (&[0xC0, 0x80], Overlong, 1),
+                             (&[0xE0, 0x9F, 0xBF], Overlong, 1),
+                             (&[0xF0, 0x8F, 0xBF, 0xBF], Overlong, 1),
+                             (&[0xED, 0xA0, 0x80], Surrogate, 1),
+                             (&[0xF4, 0x90, 0x80, 0x80], OutOfRange, 1),
+                             (&[0xF5, 0x80, 0x80, 0x80], OutOfRange, 1),
+                             (&[0xF8, 0x88, 0x80, 0x80, 0x80], InvalidLeadByte, 1),
+                             (&[0xFF], InvalidLeadByte, 1),
+                             (&[0xF0, 0x9F, 0x98, 0x41], TruncatedSequence, 3)].iter() {
+        let e = decode_utf8(bs.iter().cloned()).next().unwrap().unwrap_err();
+        assert_eq!((kind, len, None), (e.kind(), e.len(), e.needed()), "bytes = {:02X?}", bs);
+        assert_eq!(Some((0, len, e)), first_invalid(bs), "bytes = {:02X?}", bs);
+    }
+    let e = decode_utf8(b"\xF0\x9F\x98".iter().cloned()).next().unwrap().unwrap_err();
+    assert_eq!((TruncatedSequence, 3, Some(4)), (e.kind(), e.len(), e.needed()));
+    assert!(e.is_incomplete());
+}
+
+#[test]
+fn test_utf8_char_width() {
+    for c in (0..0x110000).step_by(0x1F).filter_map(from_u32) {
+        let mut buf = [0; 4];
+        assert_eq!(c.len_utf8(), utf8_char_width(c.encode_utf8(&mut buf).as_bytes()[0]), "char = {:?}", c);
+    }
+    for b in (0x80..=0xC1).chain(0xF5..=0xFF) { assert_eq!(0, utf8_char_width(b), "byte = {:02X}", b); }
+}
+
+#[test]
+fn test_surrogate_pair() {
+    for c in (0..0x110000).step_by(0x1F).filter_map(from_u32) {
+        let mut buf = [0; 2];
+        match *c.encode_utf16(&mut buf) {
+            [_] => assert_eq!(None, encode_surrogate_pair(c), "char = {:?}", c),
+            [hi, lo] => {
+                assert_eq!(Some((hi, lo)), encode_surrogate_pair(c), "char = {:?}", c);
+                assert_eq!(Some(c), decode_surrogate_pair(hi, lo), "char = {:?}", c);
+            },
+            _ => unreachable!(),
+        }
+    }
+    for &(hi, lo) in [(0xDC00, 0xDC00), (0xD800, 0xD800), (0xDC00, 0xD800), (0x41, 0xDC00), (0xD800, 0x41)].iter() {
+        assert_eq!(None, decode_surrogate_pair(hi, lo), "units = {:04X?}", (hi, lo));
+    }
+}
+
+#[test]
+fn test_code_point() {
+    for &(x, c, surrogate) in [(0x41, Some('A'), false),
+                               (0xD7FF, Some('\u{D7FF}'), false),
+                               (0xD800, None, true),
+                               (0xDFFF, None, true),
+                               (0xE000, Some('\u{E000}'), false),
+                               (0x10FFFF, Some('\u{10FFFF}'), false)].iter() {
+        let p = CodePoint::from_u32(x).unwrap();
+        assert_eq!((x, c, surrogate), (p.to_u32(), p.to_char(), p.is_surrogate()), "x = {:X}", x);
+        if let Some(c) = c { assert_eq!(p, CodePoint::from(c)); }
+    }
+    assert_eq!(None, CodePoint::from_u32(0x110000));
+}
+
+#[test]
+fn test_decode_into() {
+    let mut out = ['\0'; 4];
+    assert_eq!(Ok((8, 3)), decode_into("A♥😀".as_bytes(), &mut out));
+    assert_eq!(['A', '♥', '😀', '\0'], out);
+    assert_eq!(Ok((4, 2)), decode_into("A♥😀".as_bytes(), &mut out[..2]));
+    assert_eq!(Ok((0, 0)), decode_into(b"", &mut out));
+    assert_eq!(Ok((1, 1)), decode_into(b"A\xFF", &mut out[..1]));
+    assert_eq!(invalid(ErrorKind::InvalidLeadByte, 1), decode_into(b"A\xFF", &mut out));
+    assert_eq!(incomplete(3, 2), decode_into(b"A\xE2\x99", &mut out));
+}
+
+#[test]
+fn test_encode_into() {
+    let mut out = [0; 8];
+    assert_eq!(Ok(8), encode_into(&['A', '♥', '😀'], &mut out));
+    assert_eq!("A♥😀".as_bytes(), &out[..]);
+    assert_eq!(Ok(0), encode_into(&[], &mut out));
+    assert_eq!(Err(4), encode_into(&['A', '♥', '😀'], &mut out[..7]));
+    assert_eq!(8, encoded_len_slice(&['A', '♥', '😀']));
+    assert_eq!(0, encoded_len_slice(&[]));
+    assert_eq!(Err(0), encode_into(&['♥'], &mut out[..2]));
+}
+
+#[test]
+fn test_try_encode_utf8_split() {
+    let mut buf = [0; 9];
+    let rest = {
+        let (s, rest) = 'A'.try_encode_utf8_split(&mut buf).unwrap();
+        assert_eq!("A", s);
+        let (s, rest) = '♥'.try_encode_utf8_split(rest).unwrap();
+        assert_eq!("♥", s);
+        let (bs, rest) = 0x1F600u32.try_encode_utf8_split(rest).unwrap();
+        assert_eq!("😀".as_bytes(), bs);
+        assert_eq!(None, '♥'.try_encode_utf8_split(rest));
+        rest.len()
+    };
+    assert_eq!(1, rest);
+    assert_eq!("A♥😀".as_bytes(), &buf[..8]);
+    assert_eq!(None, 0x80000000u32.try_encode_utf8_split(&mut buf));
+}
+
+#[test]
+fn test_roundtrip() {
+    assert!((0..0x110000).filter_map(from_u32).all(roundtrip_ok));
+    for &bs in [&b""[..], b"A\xE2\x99\xA5", b"A\xE2\x99A\x80\xF0\x9F\x98", b"\xC0\x80\xED\xA0\x80\xF4\x90",
+                "A♥😀\u{10FFFF}".as_bytes()].iter() {
+        assert!(decode_encode_stable(bs), "bytes = {:02X?}", bs);
+    }
+}
+
+#[test]
+fn test_into_inner() {
+    use std::iter::FromIterator;
+    use std::vec::Vec;
+    let mut it = decode_utf8(b"A\xE2\x99A".iter().cloned());
+    assert_eq!(Some(Ok('A')), it.next());
+    assert_eq!(Some(invalid(ErrorKind::TruncatedSequence, 2)), it.next());
+    let mut bs = it.into_inner();
+    assert_eq!(Some(&b'A'), bs.peek());
+    assert_eq!(&b"A"[..], &Vec::from_iter(bs)[..]);
+}
+
+#[test]
+fn test_from_peekable() {
+    let mut bs = b"\x02\xE2\x99\xA5A\x01B".iter().cloned().peekable();
+    assert_eq!(Some(&2), bs.peek());
+    let mut it = from_peekable(bs);
+    assert_eq!(Some(Ok('\u{2}')), it.next());
+    assert_eq!(Some(Ok('♥')), it.next());
+    assert_eq!(Some(Ok('A')), it.next());
+    let mut bs = it.into_inner();
+    assert_eq!(Some(&1), bs.peek());
+    bs.next();
+    assert_eq!(Some(Ok('B')), from_peekable(bs).next());
+}
+
+#[test]
+fn test_decode_utf8_legacy() {
+    use std::vec::Vec;
+    use std::iter::FromIterator;
+    use ErrorKind::*;
+
+    for &(bs, xs) in [(&b"A\xF4\x90\x80\x80\xED\xA0\x80"[..], &[Ok(0x41), Ok(0x110000), Ok(0xD800)][..]),
+                          (b"\xF7\xBF\xBF\xBF\xF8\x88\x80\x80\x80", &[Ok(0x1FFFFF), Ok(0x200000)]),
+                          (b"\xFC\x84\x80\x80\x80\x80\xFD\xBF\xBF\xBF\xBF\xBF",
+                           &[Ok(0x4000000), Ok(0x7FFFFFFF)]),
+                          (b"\xF8\x87\xBF\xBF\xBF", &[invalid(Overlong, 1), invalid(UnexpectedContinuation, 1),
+                                                  invalid(UnexpectedContinuation, 1),
+                                                  invalid(UnexpectedContinuation, 1),
+                                                  invalid(UnexpectedContinuation, 1)]),
+                          (b"\xFE\xFF\xC0", &[invalid(InvalidLeadByte, 1), invalid(InvalidLeadByte, 1),
+                                                invalid(Overlong, 1)]),
+                          (b"\xFC\x84\x80A\xFD\xBF", &[invalid(TruncatedSequence, 3), Ok(0x41),
+                                                         incomplete(6, 2)])].iter() {
+        assert_eq!(xs, &Vec::from_iter(decode_utf8_legacy(bs.iter().cloned()))[..],
+                   "bytes = {:02X?}", bs);
+    }
+    for x in 0..0x110000u32 {
+        let mut buf = [0; 4];
+        let bs = x.try_encode_utf8(&mut buf).unwrap();
+        assert_eq!(&[Ok(x)][..], &Vec::from_iter(decode_utf8_legacy(bs.iter().cloned()))[..]);
+    }
+}
+
+#[test]
+fn test_decode_utf8_strict() {
+    use std::vec::Vec;
+    use std::iter::FromIterator;
+    use ErrorKind::*;
+
+    let nonchars = Vec::from_iter((0..0x110000).filter_map(from_u32).filter(|&c| is_noncharacter(c)));
+    assert_eq!(66, nonchars.len());
+    assert!(nonchars.iter().all(|&c| c as u32 & 0xFFFF >= 0xFFFE ||
+                                     ('\u{FDD0}'..='\u{FDEF}').contains(&c)));
+    let s = "A\u{FDCF}\u{FDD0}\u{FFFD}\u{FFFE}\u{10FFFF}";
+    let rs = [Ok('A'), Ok('\u{FDCF}'), invalid(Noncharacter, 3), Ok('\u{FFFD}'), invalid(Noncharacter, 3),
+              invalid(Noncharacter, 4)];
+    assert_eq!(&rs[..], &Vec::from_iter(decode_utf8_strict(s.bytes()))[..]);
+    let mut back = Vec::from_iter(decode_utf8_strict(s.bytes()).rev());
+    back.reverse();
+    assert_eq!(&rs[..], &back[..]);
+    assert_eq!(&[invalid(InvalidLeadByte, 1), Ok('A')][..],
+               &Vec::from_iter(decode_utf8_strict(b"\xFFA".iter().cloned()))[..]);
+}
+
+#[test]
+fn test_utf8_metrics() {
+    for &(bs, chars, bytes_valid, ascii, replacement_needed) in
+        [(&b""[..], 0, 0, 0, 0), (b"Ab\xE2\x99\xA5", 3, 5, 2, 0),
+         ("A♥😀\u{0}".as_bytes(), 4, 9, 2, 0),
+         (b"A\xE2\x99B\x80\x80\xF0\x9F\x98\x80C\xF4", 4, 1, 3, 4)].iter() {
+        assert_eq!(Utf8Metrics { chars, bytes_valid, ascii, replacement_needed }, utf8_metrics(bs),
+                   "bytes = {:02X?}", bs);
+        assert_eq!(validate_up_to(bs), utf8_metrics(bs).bytes_valid);
+        assert_eq!(decode_utf8(bs.iter().cloned()).filter(Result::is_err).count(),
+                   utf8_metrics(bs).replacement_needed);
+    }
+}
+
+#[test]
+fn test_iter_slice() {
+    use std::vec::Vec;
+
+    for &bs in [&b""[..], b"A\xE2\x99\xA5", b"A\xE2\x99A\x80\xF0\x9F\x98",
+                b"\xC0\x80\xED\xA0\x80\xF4\x90"].iter() {
+        let mut it = iter_slice(bs);
+        let (mut offsets, mut rs) = (Vec::new(), Vec::new());
+        loop {
+            let offset = it.offset();
+            match it.next() { Some(r) => { offsets.push(offset); rs.push(r); }, None => break }
+        }
+        assert_eq!(bs.len(), it.offset());
+        let (is, xs): (Vec<_>, Vec<_>) = char_indices(bs).unzip();
+        assert_eq!(&is[..], &offsets[..], "bytes = {:02X?}", bs);
+        assert_eq!(&xs[..], &rs[..], "bytes = {:02X?}", bs);
+    }
+}
+
+#[test]
+fn test_decode_utf_bytes() {
+    use std::vec::Vec;
+    use std::iter::FromIterator;
+    use ErrorKind::*;
+    use Endian::*;
+
+    let s = "A\u{FEFF}😀\u{10FFFF}";
+    for &endian in [Little, Big].iter() {
+        let hs = |us: &[u16]| Vec::from_iter(us.iter().flat_map(|&u| match endian {
+            Little => u.to_le_bytes(), Big => u.to_be_bytes(),
+        }));
+        let bs = hs(&Vec::from_iter(s.encode_utf16()));
+        assert_eq!(&Vec::from_iter(s.chars().map(Ok))[..], &Vec::from_iter(decode_utf16_bytes(bs, endian))[..]);
+        let bs = hs(&[0x41, 0xD800, 0x42, 0xDC00, 0xDBFF]);
+        assert_eq!(&[Ok('A'), invalid(Surrogate, 2), Ok('B'), invalid(Surrogate, 2), incomplete(4, 2)][..],
+                   &Vec::from_iter(decode_utf16_bytes(bs, endian))[..]);
+        let mut bs = hs(&[0x41, 0xD83D]);
+        bs.push(0);
+        assert_eq!(&[Ok('A'), incomplete(4, 3)][..], &Vec::from_iter(decode_utf16_bytes(bs, endian))[..]);
+        let mut bs = hs(&[0x41]);
+        bs.push(0);
+        assert_eq!(&[Ok('A'), incomplete(2, 1)][..], &Vec::from_iter(decode_utf16_bytes(bs, endian))[..]);
+
+        let ws = |xs: &[u32]| Vec::from_iter(xs.iter().flat_map(|&x| match endian {
+            Little => x.to_le_bytes(), Big => x.to_be_bytes(),
+        }));
+        let bs = ws(&Vec::from_iter(s.chars().map(|c| c as u32)));
+        assert_eq!(&Vec::from_iter(s.chars().map(Ok))[..], &Vec::from_iter(decode_utf32_bytes(bs, endian))[..]);
+        let mut bs = ws(&[0x41, 0xDFFF, 0x110000]);
+        bs.extend_from_slice(&[0, 0, 0]);
+        assert_eq!(&[Ok('A'), invalid(Surrogate, 4), invalid(OutOfRange, 4), incomplete(4, 3)][..],
+                   &Vec::from_iter(decode_utf32_bytes(bs, endian))[..]);
+    }
+}
+
+#[test]
+fn test_push_decoder() {
+    use std::{vec, vec::Vec};
+    use std::iter::FromIterator;
+
+    for &bs in [&b""[..], b"A\xE2\x99\xA5", b"A\xE2\x99A\x80\xF0\x9F\x98",
+                b"\xC0\x80\xED\xA0\x80\xF4\x90", b"\xE0\x80A\xF0\x9F\x98\x80\xE2\xFF\xE2\xE2\x99"].iter() {
+        let mut d = PushDecoder::new();
+        let mut rs = Vec::from_iter(bs.iter().flat_map(|&b| d.push(b)));
+        rs.extend(d.flush());
+        assert_eq!(None, d.flush());
+        assert_eq!(&Vec::from_iter(decode_utf8(bs.iter().cloned()))[..], &rs[..], "bytes = {:02X?}", bs);
+    }
+
+    // Each item comes of the push of its last byte, even after an error.
+    let mut d = PushDecoder::new();
+    let bs = b"\xC2ABC\xE2\x99\xA5\xE2\x80D\xF0";
+    let pushes = Vec::from_iter(bs.iter().map(|&b| Vec::from_iter(d.push(b))));
+    assert_eq!(&[vec![], vec![invalid(ErrorKind::TruncatedSequence, 1), Ok('A')], vec![Ok('B')],
+                 vec![Ok('C')], vec![], vec![], vec![Ok('\u{2665}')], vec![], vec![],
+                 vec![invalid(ErrorKind::TruncatedSequence, 2), Ok('D')], vec![]][..], &pushes[..]);
+    assert_eq!(Some(incomplete(4, 1)), d.flush());
+    let mut d = PushDecoder::new();
+    assert_eq!(&[vec![], vec![invalid(ErrorKind::TruncatedSequence, 1)], vec![Ok('\u{E9}')]][..],
+               &Vec::from_iter(b"\xC2\xC3\xA9".iter().map(|&b| Vec::from_iter(d.push(b))))[..]);
+}
+
+#[test]
+fn test_is_ascii() {
+    let mut bs = [b'a'; 70];
+    for n in 0..bs.len() {
+        assert!(is_ascii(&bs[..n]));
+        for k in 0..n {
+            for &b in [0x80, 0xC3, 0xFF].iter() {
+                bs[k] = b;
+                assert!(!is_ascii(&bs[..n]), "bytes = {:02X?}", &bs[..n]);
+                assert!(is_ascii(&bs[..k]) && is_ascii(&bs[k+1..n]));
+                bs[k] = b'a';
+            }
+        }
+    }
+}
+
+#[test]
+fn test_utf8_len_from_leading_zeros() {
+    const LS: [u32; 33] = [0, 6, 6, 6, 6, 6, 5, 5, 5, 5, 5, 4, 4, 4, 4, 4,
+                           3, 3, 3, 3, 3, 2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1];
+    for lz in 0..33 {
+        assert_eq!(LS[lz as usize], utf8_len_from_leading_zeros(lz), "lz = {}", lz);
+        // The least and greatest values with `lz` leading zeros
+        let (lo, hi) = if 32 == lz { (0, 0) } else { (1 << (31 - lz), !0 >> lz) };
+        for &x in [lo, hi].iter() {
+            let l = [0x80, 0x800, 0x10000, 0x200000, 0x4000000, 0x80000000u32].iter()
+                .position(|&m| x < m).map_or(0, |k| k as u32 + 1);
+            assert_eq!(l, utf8_len_from_leading_zeros(x.leading_zeros()), "x = {:X}", x);
+        }
+    }
+}
+
+#[test]
+fn test_decode_utf8_with() {
+    use std::vec::Vec;
+    use std::iter::FromIterator;
+    use ErrorKind::*;
+
+    let bs = b"A\xE2\x99B\x80\xF0\x9F\x98\x80C\xF4";
+    let chars = |f: fn(InvalidSequence) -> Option<char>|
+        Vec::from_iter(decode_utf8_with(bs.iter().cloned(), f));
+    assert_eq!(Vec::from_iter(decode_utf8_lossy(bs.iter().cloned())), chars(|_| Some('\u{FFFD}')));
+    assert_eq!(Vec::from_iter(decode_utf8_filter(bs.iter().cloned())), chars(|_| None));
+    assert_eq!(&['A', '2', 'B', '1', '😀', 'C', '?'][..],
+               &chars(|e| Some(e.needed().map_or((b'0' + e.len() as u8) as char, |_| '?')))[..]);
+    let mut errors = Vec::new();
+    let mut it = decode_utf8_with(bs.iter().cloned(), |e| { errors.push(e); None });
+    assert_eq!(Some('C'), it.next_back());
+    assert_eq!(Some('A'), it.next());
+    assert_eq!(&['B', '😀'][..], &Vec::from_iter(it)[..]);
+    assert_eq!(&[incomplete(4, 1), invalid(TruncatedSequence, 2), invalid(UnexpectedContinuation, 1)][..],
+               &Vec::from_iter(errors.into_iter().map(Err::<(), _>))[..]);
+}
+
+#[test]
+fn test_decode_utf8_with_len() {
+    for &bs in [&b""[..], b"A\xE2\x99\xA5", b"A\xE2\x99A\x80\xF0\x9F\x98\x80",
+                b"\xC0\x80\xED\xA0\x80\xF4\x90",
+                "\u{7F}\u{80}\u{7FF}\u{800}\u{FFFF}\u{10000}\u{10FFFF}".as_bytes()].iter() {
+        let mut rest = bs;
+        for r in decode_utf8_with_len(bs.iter().cloned()) {
+            let l = r.map_or_else(|e| e.len(), |(_, l)| l.get());
+            assert_eq!(r.map_err(|_| ()), decode_slice(&rest[..l]).ok_or(()), "bytes = {:02X?}", bs);
+            rest = &rest[l..];
+        }
+        assert!(rest.is_empty());
+    }
+}
+
+#[test]
+fn test_try_encode_utf8_scalar() {
+    for x in (0..0x110000).chain([0x200000, 0x7FFFFFFF, 0x80000000, !0].iter().cloned()) {
+        assert_eq!(from_u32(x).is_some(), is_valid_scalar(x), "x = {:X}", x);
+        let (mut buf, mut scalar_buf) = ([0u8; MAX_UTF8_LEN], [0u8; MAX_UTF8_LEN]);
+        let bs = x.try_encode_utf8(&mut buf);
+        let scalar_bs = x.try_encode_utf8_scalar(&mut scalar_buf);
+        assert_eq!(if is_valid_scalar(x) { bs } else { None }, scalar_bs, "x = {:X}", x);
+        if let Some(c) = from_u32(x) {
+            let mut buf = [0u8; 4];
+            assert_eq!(c.try_encode_utf8_scalar(&mut buf).map(|s| s.as_bytes()), scalar_bs.as_deref());
+        }
+    }
+    assert_eq!(None, 'A'.try_encode_utf8_scalar(&mut []));
+    assert_eq!(None, 0x41.try_encode_utf8_scalar(&mut []));
+}
+
+#[test]
+fn test_split_valid() {
+    for &(bs, l) in [(&b""[..], 0), (b"A\xE2\x99\xA5", 4), (b"A\xE2\x99", 1),
+                     (b"A\xE2\x99\xA5\xF0\x9F\x98", 4), (b"\x80A", 0), (b"AB\xED\xA0\x80", 2)].iter() {
+        let (s, rest) = split_valid(bs);
+        assert_eq!((&bs[..l], &bs[l..]), (s.as_bytes(), rest), "bytes = {:02X?}", bs);
+    }
+}
+
+#[test]
+fn test_last_char() {
+    for &bs in [&b""[..], b"A\xE2\x99\xA5", b"A\xE2\x99", b"A\xE2\x99\xA5\xF0\x9F\x98", b"\x80",
+                b"A\x80\x80", b"AB\xED\xA0\x80", b"\xF0\x9F\x98\x80", b"\xF0\x9F\x98\x80\x80",
+                b"\n"].iter() {
+        let last = decode_utf8(bs.iter().cloned()).fold(None, |_, r| Some(r));
+        assert_eq!(last, last_char(bs), "bytes = {:02X?}", bs);
+        assert_eq!(decode_slice_back(bs).map(|(c, _)| c), last_char(bs).and_then(Result::ok),
+                   "bytes = {:02X?}", bs);
+    }
+}
+
+#[test]
+fn test_utf16_len() {
+    use ErrorKind::*;
+
+    for &c in ['\0', 'A', '\u{7F}', '\u{80}', '♥', '\u{FFFF}', '\u{10000}', '😀', '\u{10FFFF}'].iter() {
+        assert_eq!(c.len_utf16(), char_utf16_len(c), "char = {:?}", c);
+    }
+    for &s in ["", "A", "A♥😀\u{10FFFF}", "the quick brown fox jumps over the lazy dog 😀"].iter() {
+        assert_eq!(Ok(s.encode_utf16().count()), utf16_len(s.as_bytes()), "str = {:?}", s);
+    }
+    assert_eq!(invalid(TruncatedSequence, 2), utf16_len(b"A\xE2\x99A\xFF"));
+    assert_eq!(incomplete(4, 3), utf16_len(b"AB\xF0\x9F\x98"));
+}
+
+#[test]
+fn test_to_utf8_array() {
+    for c in (0..0x110000).filter_map(from_u32) {
+        let (bs, l) = c.to_utf8_array();
+        let mut buf = [0; 4];
+        assert_eq!(c.encode_utf8(&mut buf).as_bytes(), &bs[..l], "char = {:?}", c);
+    }
+    for &(x, l) in [(0xD800u32, 3), (0x10FFFF, 4), (0x110000, 0), (!0, 0)].iter() {
+        assert_eq!(l, x.to_utf8_array().1, "x = {:X}", x);
+    }
+}
+
+#[test]
+fn test_encode_utf8_chunks() {
+    use std::vec::Vec;
+    use std::iter::FromIterator;
+
+    let s = "A\u{7F}\u{80}♥\u{FFFF}😀\u{10FFFF}";
+    let mut bs = Vec::new();
+    for (c, buf, l) in encode_utf8_chunks(s.chars()) {
+        assert_eq!(c.len_utf8(), l, "char = {:?}", c);
+        bs.extend_from_slice(&buf[..l]);
+    }
+    assert_eq!(s.as_bytes(), &bs[..]);
+    assert_eq!(Vec::from_iter(encode_utf8_chunks(s.chars()).rev()),
+               Vec::from_iter(encode_utf8_chunks(Vec::from_iter(s.chars().rev()))));
+}
+
+#[test]
+fn test_decode_slice_resumable() {
+    use std::vec::Vec;
+    use std::iter::FromIterator;
+    use ErrorKind::*;
+
+    for &(bs, l) in [(&b""[..], 0), (b"A\xE2\x99\xA5", 0), (b"A\xE2\x99", 2),
+                     (b"\xF0\x9F\x98", 3), (b"A\xE2", 1), (b"A\xE2\x99A", 0), (b"A\xED\xA0", 0),
+                     (b"A\x80", 0)].iter() {
+        let (it, tail) = decode_slice_resumable(bs);
+        assert_eq!(&bs[bs.len() - l..], tail, "bytes = {:02X?}", bs);
+        let rs = Vec::from_iter(it);
+        assert!(rs.iter().all(|r| r.map_or_else(|e| !e.is_incomplete(), |_| true)), "bytes = {:02X?}", bs);
+        assert_eq!(&Vec::from_iter(iter_slice(&bs[..bs.len() - l]))[..], &rs[..]);
+    }
+    let (it, tail) = decode_slice_resumable(b"A\xED\xA0");
+    assert_eq!((&[Ok('A'), invalid(Surrogate, 1), invalid(UnexpectedContinuation, 1)][..], &b""[..]),
+               (&Vec::from_iter(it)[..], tail));
+
+    // Carrying the tail forward decodes chunks as one.
+    let s = "A♥😀\u{10FFFF}B";
+    for k in 0..s.len() {
+        let (it, tail) = decode_slice_resumable(&s.as_bytes()[..k]);
+        let mut cs = Vec::from_iter(it);
+        let mut rest = Vec::from(tail);
+        rest.extend_from_slice(&s.as_bytes()[k..]);
+        cs.extend(iter_slice(&rest));
+        assert_eq!(&Vec::from_iter(s.chars().map(Ok))[..], &cs[..], "k = {}", k);
+    }
+}
+
+#[test]
+fn test_decode_utf8_cstr() {
+    use std::vec::Vec;
+    use std::iter::FromIterator;
+    use ErrorKind::*;
+
+    for &(bs, rs, nul) in [(&b""[..], &[][..], None), (b"\0", &[], Some(0)),
+                               (b"A\xE2\x99\xA5\0B", &[Ok('A'), Ok('♥')], Some(4)),
+                               (b"A\xE2\x99\0\0", &[Ok('A'), invalid(TruncatedSequence, 2)], Some(3)),
+                               (b"A\xE2\x99", &[Ok('A'), incomplete(3, 2)], None),
+                               (b"\xC0\x80", &[invalid(Overlong, 1), invalid(UnexpectedContinuation, 1)],
+                                None)].iter() {
+        let (it, i) = decode_utf8_cstr(bs);
+        assert_eq!((rs, nul), (&Vec::from_iter(it)[..], i), "bytes = {:02X?}", bs);
+    }
+}
+
+#[test]
+fn test_utf8_eq() {
+    for &(bs, s, eq, eq_lossy) in [(&b""[..], "", true, true), (b"A\xE2\x99\xA5", "A♥", true, true),
+                                   (b"A\xE2\x99\xA5", "A♥B", false, false), (b"A\xE2\x99", "A", false, false),
+                                   (b"A\xE2\x99", "A\u{FFFD}", false, true),
+                                   (b"\xC0\x80", "\u{FFFD}\u{FFFD}", false, true),
+                                   (b"\xC0\x80", "\u{FFFD}", false, false)].iter() {
+        assert_eq!((eq, eq_lossy), (utf8_eq(bs, s), utf8_eq_lossy(bs, s)),
+                   "bytes = {:02X?}, str = {:?}", bs, s);
+    }
+}
+
+#[test]
+fn test_invalid_subpart_len() {
+    for &(bs, l) in [(&b""[..], 0), (b"A", 0), (b"\xE2\x99\xA5", 0), (b"\xE2\x99", 0),
+                     (b"\xE2\x99A", 2), (b"\x80", 1), (b"\xC0\x80", 1), (b"\xE0\x80", 1),
+                     (b"\xED\xA0\x80", 1), (b"\xF4\x90", 1), (b"\xF0\x9F\x98A", 3), (b"\xFF", 1)].iter() {
+        assert_eq!(NonZeroUsize::new(l), invalid_subpart_len(bs), "bytes = {:02X?}", bs);
+    }
+}
+
+#[test]
+fn test_decode_lossy_into() {
+    let bs = b"A\xE2\x99\xA5\xE2\x99B\xFF\xF0\x9F\x98\x80";
+    let s = "A♥\u{FFFD}B\u{FFFD}😀";
+    let mut out = [0; 32];
+    assert_eq!((bs.len(), s.len()), decode_lossy_into(bs, &mut out));
+    assert_eq!(s.as_bytes(), &out[..s.len()]);
+    // Each character, or replacement, is written entire or not at all: the ends of the
+    // items in the input and the output
+    let ends = [(0, 0), (1, 1), (4, 4), (6, 7), (7, 8), (8, 11), (12, 15)];
+    for n in 0..s.len() {
+        let &(i, l) = ends.iter().rev().find(|&&(_, l)| l <= n).unwrap();
+        assert_eq!((i, l), decode_lossy_into(bs, &mut out[..n]), "n = {}", n);
+        assert_eq!(&s.as_bytes()[..l], &out[..l]);
+    }
+}
+
+#[test]
+fn test_max_len() {
+    assert_eq!(MAX_UTF8_LEN, (0..0x110000).filter_map(from_u32).map(char::len_utf8).max().unwrap());
+    assert_eq!(MAX_UTF16_LEN, (0..0x110000).filter_map(from_u32).map(char::len_utf16).max().unwrap());
+    let bs = [0xFD, 0xBF, 0xBF, 0xBF, 0xBF, 0xBF];
+    assert_eq!(MAX_UTF8_LEN_LEGACY, bs.len());
+    assert_eq!(Some(Ok(0x7FFFFFFF)), decode_utf8_legacy(bs.iter().cloned()).next());
+}
+
+#[test]
+fn test_decode_utf8_skip_bom() {
+    use std::vec::Vec;
+    use std::iter::FromIterator;
+
+    for &(s, t) in [("", ""), ("\u{FEFF}", ""), ("\u{FEFF}A", "A"), ("A\u{FEFF}", "A\u{FEFF}"),
+                    ("\u{FEFF}\u{FEFF}A\u{FEFF}", "\u{FEFF}A\u{FEFF}"), ("♥", "♥")].iter() {
+        assert_eq!(Vec::from_iter(t.chars().map(Ok)), Vec::from_iter(decode_utf8_skip_bom(s.bytes())),
+                   "str = {:?}", s);
+    }
+    assert_eq!(&[incomplete(3, 2)][..],
+               &Vec::from_iter(decode_utf8_skip_bom(b"\xEF\xBB".iter().cloned()))[..]);
+}
+
+#[test]
+fn test_validate_detailed() {
+    use ErrorKind::*;
+
+    for &(bs, r) in [(&b""[..], Ok(())), (b"A\xE2\x99\xA5", Ok(())),
+                     (b"A\xE2\x99", Err((1, TruncatedSequence))), (b"AB\x80", Err((2, UnexpectedContinuation))),
+                     (b"\xC0\x80", Err((0, Overlong))),
+                     (b"\xE0\x80\x80", Err((0, Overlong))), (b"A\xED\xA0\x80", Err((1, Surrogate))),
+                     (b"\xF4\x90\x80\x80", Err((0, OutOfRange))), (b"A\xFF", Err((1, InvalidLeadByte))),
+                     (b"\xE2\x99A\xFF", Err((0, TruncatedSequence)))].iter() {
+        assert_eq!(r, validate_detailed(bs), "bytes = {:02X?}", bs);
+        assert_eq!(iter_slice(bs).find_map(Result::err).map(|e| e.kind()), r.err().map(|(_, k)| k));
+    }
+}
+
+#[test]
+fn test_revalidate() {
+    for &s in ["", "A", "\u{0}\u{7F}\u{80}\u{7FF}\u{800}\u{D7FF}\u{E000}\u{FFFF}\u{10000}\u{10FFFF}"].iter() {
+        assert_eq!(Ok(()), revalidate(s), "s = {:?}", s);
+    }
+}
+
+#[test]
+fn test_split_on() {
+    use std::vec::Vec;
+    use std::iter::FromIterator;
+    for &s in ["", " ", "a", "a b", " a  b ", "\u{3B1}\u{3000}\u{1F600} x\u{3B2}", "  "].iter() {
+        assert_eq!(Vec::from_iter(s.split(char::is_whitespace)),
+                   Vec::from_iter(split_on(decode_utf8(s.bytes()), char::is_whitespace)
+                                  .map(|(i, j)| &s[i..j])), "s = {:?}", s);
+    }
+    assert_eq!(&[(0, 3), (4, 6), (7, 7)][..],
+               &Vec::from_iter(split_on(decode_utf8(b"a\xFFb,\xE2\x99,".iter().cloned()), |c| ',' == c))[..]);
+}
+
+#[test]
+fn test_decode_utf8_classified() {
+    use std::vec::Vec;
+    use std::iter::FromIterator;
+    use ScalarClass::*;
+
+    for &(c, k) in [('\u{0}', Ascii), ('\u{7F}', Ascii), ('\u{80}', Latin1), ('\u{FF}', Latin1), ('\u{100}', Bmp),
+                    ('\u{FFFF}', Bmp), ('\u{10000}', Astral), ('\u{10FFFF}', Astral)].iter() {
+        assert_eq!(k, ScalarClass::of(c), "c = {:?}", c);
+    }
+    let bs = b"A\xC3\xA9\xE2\x99\xA5\xFF\xF0\x9F\x98\x80";
+    let mut rs = Vec::from_iter(decode_utf8_classified(bs.iter().cloned()));
+    assert_eq!(&[Ok(('A', Ascii)), Ok(('\u{E9}', Latin1)), Ok(('\u{2665}', Bmp)),
+                 invalid(ErrorKind::InvalidLeadByte, 1), Ok(('\u{1F600}', Astral))][..], &rs[..]);
+    rs.reverse();
+    assert_eq!(rs, Vec::from_iter(decode_utf8_classified(bs.iter().cloned()).rev()));
+}
+
+#[test]
+fn test_decode_utf8_no_controls() {
+    use std::vec::Vec;
+    use std::iter::FromIterator;
+    use ErrorKind::*;
+
+    let controls = Vec::from_iter((0..0x110000).filter_map(from_u32).filter(|&c| is_control_forbidden(c)));
+    assert_eq!(64, controls.len());
+    assert!(controls.iter().all(|&c| c.is_control() && '\x7F' != c));
+    let s = "A\0\t\n\r\x1F \x7F\u{80}\u{9F}\u{A0}";
+    let rs = [Ok('A'), invalid(Control, 1), invalid(Control, 1), invalid(Control, 1), invalid(Control, 1),
+              invalid(Control, 1), Ok(' '), Ok('\x7F'), invalid(Control, 2), invalid(Control, 2), Ok('\u{A0}')];
+    assert_eq!(&rs[..], &Vec::from_iter(decode_utf8_no_controls(s.bytes()))[..]);
+    let mut back = Vec::from_iter(decode_utf8_no_controls(s.bytes()).rev());
+    back.reverse();
+    assert_eq!(&rs[..], &back[..]);
+    let rs = [Ok('A'), invalid(Control, 1), Ok('\t'), Ok('\n'), Ok('\r'),
+              invalid(Control, 1), Ok(' '), Ok('\x7F'), invalid(Control, 2), invalid(Control, 2), Ok('\u{A0}')];
+    assert_eq!(&rs[..], &Vec::from_iter(decode_utf8_no_controls_but_whitespace(s.bytes()))[..]);
+    assert_eq!(&[Ok('A'), invalid(InvalidLeadByte, 1)][..],
+               &Vec::from_iter(decode_utf8_no_controls(b"A\xFF".iter().cloned()))[..]);
+}
+
+#[test]
+fn test_count_invalid_subparts() {
+    for &bs in [&b""[..], b"A", b"\xE2\x99\xA5", b"\xFF", b"\xE2\x99", b"A\xE2\x99A\x80\xF0\x9F\x98",
+                b"\xC0\x80\xED\xA0\x80\xF4\x90\x80\x80", b"\x80\x80\x80", b"\xF0\x9F\x98\x80\xF0\x9F"].iter() {
+        assert_eq!(decode_utf8_lossy(bs.iter().cloned()).filter(|&c| '\u{FFFD}' == c).count(),
+                   count_invalid_subparts(bs), "bytes = {:02X?}", bs);
+    }
+}
+
+#[test]
+fn test_utf8() {
+    use std::string::{String, ToString};
+
+    assert_eq!(Utf8(b"A\xE2\x99\xA5"), *"A\u{2665}");
+    assert_eq!(Utf8(b"A\xE2\x99\xA5"), "A\u{2665}");
+    assert_eq!(Utf8(b""), "");
+    assert_ne!(Utf8(b"A\xE2\x99"), "A\u{FFFD}");
+    assert_ne!(Utf8(b"A\xFF"), "A\u{FFFD}");
+    assert_ne!(Utf8(b"A"), "AB");
+    for &bs in [&b""[..], b"A\xE2\x99\xA5", b"A\xE2\x99A\x80\xF0\x9F\x98", b"\xC0\x80\xED\xA0\x80B"].iter() {
+        assert_eq!(String::from_utf8_lossy(bs), Utf8(bs).to_string(), "bytes = {:02X?}", bs);
+    }
+}
+
+#[test]
+fn test_rchars() {
+    use std::vec::Vec;
+    use std::iter::FromIterator;
+
+    let s = "A\u{E9}\u{2665}\u{1F600}";
+    assert_eq!(Vec::from_iter(s.chars().rev().map(Ok)), Vec::from_iter(rchars(s.as_bytes())));
+    for &bs in [&b""[..], b"A\xE2\x99A\x80\xF0\x9F\x98", b"\xC0\x80\xED\xA0\x80B", b"\x80\x80\xE2\x99\xA5",
+                b"\xF4\x90\x80\x80\xFF\xE2"].iter() {
+        let mut rs = Vec::from_iter(iter_slice(bs));
+        rs.reverse();
+        assert_eq!(rs, Vec::from_iter(rchars(bs)), "bytes = {:02X?}", bs);
+        assert_eq!(rs.first().cloned(), last_char(bs));
+        rs.reverse();
+        assert_eq!(rs, Vec::from_iter(rchars(bs).rev()), "bytes = {:02X?}", bs);
+    }
+    // The same whatever the byte after a sequence which `next_back` cuts short
+    let bytes = [0x41, 0x80, 0x9F, 0xA0, 0xBF, 0xC2, 0xE0, 0xE2, 0xED, 0xF0, 0xF4, 0xFF];
+    for &b0 in bytes.iter() { for &b1 in bytes.iter() { for &b2 in bytes.iter() {
+        let bs = [b0, b1, b2];
+        let mut rs = Vec::from_iter(iter_slice(&bs));
+        // Whatever is taken off the back first, the front then ends where it did.
+        for n in 0..rs.len() {
+            let mut it = rchars(&bs);
+            let mut back = Vec::from_iter(it.by_ref().take(n));
+            back.reverse();
+            let mut front = Vec::from_iter(it.rev());
+            front.extend(back);
+            assert_eq!(rs, front, "bytes = {:02X?}", bs);
+        }
+        rs.reverse();
+        assert_eq!(rs, Vec::from_iter(rchars(&bs)), "bytes = {:02X?}", bs);
+    } } }
+}
+
+#[test]
+fn test_try_encode_utf8_modified() {
+    use std::vec::Vec;
+    use std::iter::FromIterator;
+    use ErrorKind::*;
+
+    let mut buf = [0; MAX_UTF8_LEN];
+    for &(c, bs) in [('\0', &b"\xC0\x80"[..]), ('A', b"A"), ('\u{2665}', b"\xE2\x99\xA5"),
+                     ('\u{1F600}', b"\xF0\x9F\x98\x80")].iter() {
+        assert_eq!(Some(bs), c.try_encode_utf8_modified(&mut buf).map(|bs| &*bs), "c = {:?}", c);
+        assert_eq!(Some(bs), (c as u32).try_encode_utf8_modified(&mut buf).map(|bs| &*bs), "c = {:?}", c);
+        assert_eq!(Some(Ok(c)), decode_utf8_modified(bs.iter().cloned()).next());
+        assert_eq!(None, c.try_encode_utf8_modified(&mut buf[..bs.len()-1]));
+    }
+    assert_eq!(None, 0x110000.try_encode_utf8_modified(&mut buf));
+    for &(bs, rs) in [(b"\0A" as &[u8], &[invalid(InvalidLeadByte, 1), Ok('A')] as &[_]),
+                      (b"\xC0\x81", &[invalid(Overlong, 1), invalid(UnexpectedContinuation, 1)]),
+                      (b"\xC0A", &[invalid(TruncatedSequence, 1), Ok('A')]),
+                      (b"\xC0", &[incomplete(2, 1)]),
+                      (b"A\xC0\x80\xED\xA0\x80",
+                       &[Ok('A'), Ok('\0'), invalid(Surrogate, 1), invalid(UnexpectedContinuation, 1),
+                         invalid(UnexpectedContinuation, 1)])].iter() {
+        assert_eq!(rs, &Vec::from_iter(decode_utf8_modified(bs.iter().cloned()))[..], "bytes = {:02X?}", bs);
+    }
+}
+
+#[test]
+fn test_take_chars() {
+    use ErrorKind::*;
+
+    let s = "A\u{E9}\u{2665}\u{1F600}";
+    for n in 0..6 {
+        let k = s.char_indices().nth(n).map_or(s.len(), |(k, _)| k);
+        assert_eq!(Ok((&s[..k], &s.as_bytes()[k..])), take_chars(s.as_bytes(), n), "n = {}", n);
+    }
+    assert_eq!(Ok(("A", &b"\xFF"[..])), take_chars(b"A\xFF", 1));
+    assert_eq!(invalid(InvalidLeadByte, 1), take_chars(b"A\xFF", 2));
+    assert_eq!(incomplete(3, 2), take_chars(b"A\xE2\x99", 2));
+    assert_eq!(Ok(("", &b""[..])), take_chars(b"", 1));
+}
+
+#[test]
+fn test_slice_decoder_double_ended() {
+    use std::vec::Vec;
+    use std::iter::FromIterator;
+    use ErrorKind::*;
+
+    let bytes = [0x41, 0x80, 0x9F, 0xA0, 0xBF, 0xC2, 0xE0, 0xE2, 0xED, 0xF0, 0xF4, 0xFF];
+    for &b0 in bytes.iter() { for &b1 in bytes.iter() { for &b2 in bytes.iter() { for &b3 in bytes.iter() {
+        let bs = [b0, b1, b2, b3];
+        let rs = Vec::from_iter(decode_utf8(bs.iter().cloned()));
+        assert_eq!(rs, Vec::from_iter(iter_slice(&bs)), "bytes = {:02X?}", bs);
+        // Forward and backward meet wherever, neither skipping nor repeating an item.
+        for n in 0..=rs.len() {
+            let mut it = iter_slice(&bs);
+            let mut front = Vec::from_iter(it.by_ref().take(n));
+            let mut back = Vec::from_iter(it.by_ref().rev());
+            back.reverse();
+            front.extend(back);
+            assert_eq!(rs, front, "bytes = {:02X?}, n = {}", bs, n);
+            assert_eq!(None, it.next());
+        }
+    } } } }
+    assert_eq!(Some(invalid(Surrogate, 1)), iter_slice(b"\xED\xA0").nth_back(1));
+    let (mut it, tail) = decode_slice_resumable(b"\xE2\xE2\x99");
+    assert_eq!((Some(invalid(TruncatedSequence, 1)), &b"\xE2\x99"[..]), (it.next(), tail));
+}
+
+#[test]
+fn test_slice_decoder_into_exact() {
+    use std::vec::Vec;
+    use std::iter::FromIterator;
+    use ErrorKind::*;
+
+    let s = "A\u{E9}\u{2665}\u{1F600}";
+    let mut it = iter_slice(s.as_bytes()).into_exact().unwrap();
+    assert_eq!(4, it.len());
+    assert_eq!(Some('\u{1F600}'), it.next_back());
+    assert_eq!(Some('A'), it.next());
+    assert_eq!(2, it.len());
+    assert_eq!(&['\u{E9}', '\u{2665}'][..], &Vec::from_iter(it)[..]);
+    let mut it = iter_slice(b"\xFFA\xE2\x99\xA5");
+    it.next();
+    assert_eq!(&['A', '\u{2665}'][..], &Vec::from_iter(it.into_exact().unwrap())[..]);
+    assert_eq!(invalid(UnexpectedContinuation, 1), iter_slice(b"A\x80").into_exact().map(|_| 'A'));
+    assert_eq!(incomplete(3, 2), iter_slice(b"A\xE2\x99").into_exact().map(|_| 'A'));
 }
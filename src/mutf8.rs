@@ -0,0 +1,83 @@
+//! Modified UTF-8, as Java uses, which is CESU-8 but for encoding U+0000 as `C0 80`, so
+//! that no byte is 0
+
+use core::iter;
+
+use cesu8::{decode_cesu8, encode_cesu8, DecodeCesu8, EncodeCesu8};
+use InvalidSequence;
+
+/// An iterator over an iterator of bytes of the characters the bytes represent as Modified
+/// UTF-8
+#[derive(Clone, Debug)]
+pub struct DecodeMutf8<I: Iterator<Item = u8>>(DecodeCesu8<I>);
+
+/// Decodes an `Iterator` of bytes as Modified UTF-8. A 0 byte is invalid, as are the
+/// overlong forms but for `C0 80`.
+#[inline]
+pub fn decode_mutf8<I: IntoIterator<Item = u8>>(i: I) -> DecodeMutf8<I::IntoIter> {
+    let mut d = decode_cesu8(i);
+    d.mutf8 = true;
+    DecodeMutf8(d)
+}
+
+impl<I: Iterator<Item = u8>> Iterator for DecodeMutf8<I> {
+    type Item = Result<char, InvalidSequence>;
+    #[inline]
+    fn next(&mut self) -> Option<Result<char, InvalidSequence>> { self.0.next() }
+}
+
+impl<I: iter::FusedIterator<Item = u8>> iter::FusedIterator for DecodeMutf8<I> {}
+
+/// An iterator over an iterator of characters of the bytes which represent them as
+/// Modified UTF-8
+#[derive(Clone, Debug)]
+pub struct EncodeMutf8<I: Iterator<Item = char>>(EncodeCesu8<I>);
+
+/// Encodes an `Iterator` of characters as Modified UTF-8.
+#[inline]
+pub fn encode_mutf8<I: IntoIterator<Item = char>>(i: I) -> EncodeMutf8<I::IntoIter> {
+    let mut e = encode_cesu8(i);
+    e.mutf8 = true;
+    EncodeMutf8(e)
+}
+
+impl<I: Iterator<Item = char>> Iterator for EncodeMutf8<I> {
+    type Item = u8;
+    #[inline]
+    fn next(&mut self) -> Option<u8> { self.0.next() }
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) { self.0.size_hint() }
+}
+
+impl<I: iter::FusedIterator<Item = char>> iter::FusedIterator for EncodeMutf8<I> {}
+
+#[test]
+fn test_mutf8() {
+    use std::vec::Vec;
+    use std::iter::FromIterator;
+    use {incomplete, invalid, ErrorKind::*};
+
+    for &(s, bs) in [("", &[] as &[u8]),
+                     ("A\0♥", b"A\xC0\x80\xE2\x99\xA5"),
+                     ("😀\0", b"\xED\xA0\xBD\xED\xB8\x80\xC0\x80")].iter() {
+        assert_eq!(bs, &Vec::from_iter(encode_mutf8(s.chars()))[..], "str = {:?}", s);
+        assert_eq!(Vec::from_iter(s.chars().map(Ok)), Vec::from_iter(decode_mutf8(bs.iter().cloned())),
+                   "str = {:?}", s);
+    }
+    for &(bs, rs) in [(b"\0A" as &[u8], &[invalid(InvalidLeadByte, 1), Ok('A')] as &[_]),
+                      (b"\xC0\x81", &[invalid(Overlong, 1), invalid(UnexpectedContinuation, 1)]),
+                      (b"\xC1\x81", &[invalid(Overlong, 1), invalid(UnexpectedContinuation, 1)]),
+                      (b"\xE0\x81\x81",
+                       &[invalid(Overlong, 1), invalid(UnexpectedContinuation, 1),
+                         invalid(UnexpectedContinuation, 1)]),
+                      (b"\xC0", &[incomplete(2, 1)]),
+                      (b"\xED\xA0\xBD\xC0", &[invalid(Surrogate, 3), incomplete(2, 1)]),
+                      (b"\xED\xA0\xBD\xE2\x99", &[invalid(Surrogate, 3), incomplete(3, 2)]),
+                      (b"\xED\xA0\xBD\xED\xA0", &[invalid(Surrogate, 3), incomplete(3, 2)]),
+                      (b"\xED\xA0\xBD\xED\xB8", &[incomplete(6, 5)]),
+                      (b"\xF0\x9F\x98\x80",
+                       &[invalid(InvalidLeadByte, 1), invalid(UnexpectedContinuation, 1),
+                         invalid(UnexpectedContinuation, 1), invalid(UnexpectedContinuation, 1)])].iter() {
+        assert_eq!(rs, &Vec::from_iter(decode_mutf8(bs.iter().cloned()))[..], "bytes = {:02X?}", bs);
+    }
+}
@@ -0,0 +1,67 @@
+//! `proptest` strategies of buffers of valid and of malformed UTF-8, to test decoders
+//! against
+
+use std::vec::Vec;
+use std::vec;
+
+use proptest::prelude::*;
+
+use UtfExt;
+
+/// Strategy of buffers of valid UTF-8, of up to 64 characters of any length
+pub fn arb_valid_utf8() -> impl Strategy<Value = Vec<u8>> {
+    proptest::collection::vec(any::<char>(), 0..64).prop_map(::encode_to_vec)
+}
+
+/// Strategy of buffers of UTF-8 which are invalid for a malformed sequence in the midst of
+/// valid UTF-8: a truncated sequence, an overlong form, a stray continuation byte, a
+/// surrogate, a code point above U+10FFFF, or a byte which never occurs in UTF-8
+pub fn arb_malformed_utf8() -> impl Strategy<Value = Vec<u8>> {
+    (arb_valid_utf8(), arb_malformed_sequence(), arb_valid_utf8()).prop_map(|(mut bs, m, rest)| {
+        bs.extend(m);
+        bs.extend(rest);
+        bs
+    })
+}
+
+/// Strategy of single malformed sequences, invalid whatever follows
+fn arb_malformed_sequence() -> impl Strategy<Value = Vec<u8>> {
+    let cont = || 0x80..=0xBFu8;
+    prop_oneof![
+        // Truncated
+        (any::<char>().prop_filter("multibyte", |c| c.len_utf8() > 1), any::<prop::sample::Index>())
+            .prop_map(|(c, k)| {
+                let (bs, l) = c.to_utf8_array();
+                bs[..1 + k.index(l - 1)].to_vec()
+            }),
+        // Overlong
+        (0xC0..=0xC1u8, cont()).prop_map(|(b0, b1)| vec![b0, b1]),
+        (0x80..=0x9Fu8, cont()).prop_map(|(b1, b2)| vec![0xE0, b1, b2]),
+        (0x80..=0x8Fu8, cont(), cont()).prop_map(|(b1, b2, b3)| vec![0xF0, b1, b2, b3]),
+        // Stray continuation
+        cont().prop_map(|b| vec![b]),
+        // Surrogate
+        (0xA0..=0xBFu8, cont()).prop_map(|(b1, b2)| vec![0xED, b1, b2]),
+        // Above U+10FFFF
+        (0x90..=0xBFu8, cont(), cont()).prop_map(|(b1, b2, b3)| vec![0xF4, b1, b2, b3]),
+        (0xF5..=0xF7u8, cont(), cont(), cont()).prop_map(|(b0, b1, b2, b3)| vec![b0, b1, b2, b3]),
+        // Never in UTF-8
+        (0xF8..=0xFFu8).prop_map(|b| vec![b]),
+    ]
+}
+
+#[cfg(test)]
+proptest! {
+    #[test]
+    fn test_arb_valid_utf8(bs in arb_valid_utf8()) {
+        prop_assert!(::validate(&bs));
+        prop_assert!(::decode_encode_stable(&bs));
+    }
+
+    #[test]
+    fn test_arb_malformed_utf8(bs in arb_malformed_utf8()) {
+        prop_assert!(!::validate(&bs));
+        prop_assert!(::decode_encode_stable(&bs));
+        prop_assert_eq!(::std::str::from_utf8(&bs).unwrap_err().valid_up_to(), ::validate_up_to(&bs));
+    }
+}
@@ -0,0 +1,97 @@
+//! WTF-8, which is UTF-8 extended to encode unpaired surrogates, as ill-formed UTF-16
+//! may hold
+
+use core::iter;
+
+use {combine_surrogates, decode_utf8_u32, DecodeError, DecodeUtf8U32, ErrorKind, InvalidSequence, UtfExt};
+
+/// An iterator over an iterator of bytes of the code points the bytes represent as WTF-8
+#[derive(Clone, Debug)]
+pub struct DecodeWtf8<I: Iterator<Item = u8>>(DecodeUtf8U32<I>);
+
+/// Decodes an `Iterator` of bytes as WTF-8, which admits surrogate code points where UTF-8
+/// doesn't, so the code points are not all `char`s. A surrogate pair encoded as two
+/// surrogates, which WTF-8 forbids, comes out as two surrogates.
+#[inline]
+pub fn decode_wtf8<I: IntoIterator<Item = u8>>(i: I) -> DecodeWtf8<I::IntoIter> {
+    DecodeWtf8(decode_utf8_u32(i))
+}
+
+impl<I: Iterator<Item = u8>> Iterator for DecodeWtf8<I> {
+    type Item = Result<u32, InvalidSequence>;
+    #[inline]
+    fn next(&mut self) -> Option<Result<u32, InvalidSequence>> { self.0.next() }
+}
+
+impl<I: iter::FusedIterator<Item = u8>> iter::FusedIterator for DecodeWtf8<I> {}
+
+/// An iterator over an iterator of code points of the bytes which represent them as WTF-8
+#[derive(Clone, Debug)]
+pub struct EncodeWtf8<I: Iterator<Item = u32>> {
+    iter: iter::Peekable<I>,
+    buf: [u8; 4],
+    pos: u8,
+    end: u8,
+}
+
+/// Encodes an `Iterator` of code points as WTF-8: surrogates take the 3-byte form they
+/// would in UTF-8, but a surrogate pair is encoded as the supplementary character it
+/// represents. Code points above U+10FFFF are each an `InvalidSequence`.
+#[inline]
+pub fn encode_wtf8<I: IntoIterator<Item = u32>>(i: I) -> EncodeWtf8<I::IntoIter> {
+    EncodeWtf8 { iter: i.into_iter().peekable(), buf: [0; 4], pos: 0, end: 0 }
+}
+
+impl<I: Iterator<Item = u32>> Iterator for EncodeWtf8<I> {
+    type Item = Result<u8, InvalidSequence>;
+    fn next(&mut self) -> Option<Result<u8, InvalidSequence>> {
+        if self.pos == self.end {
+            let mut x = self.iter.next()?;
+            if x > 0x10FFFF { return Some(Err(DecodeError::invalid(ErrorKind::OutOfRange, 1))) }
+            if let 0xD800..=0xDBFF = x {
+                if let Some(&y @ 0xDC00..=0xDFFF) = self.iter.peek() {
+                    self.iter.next();
+                    x = combine_surrogates(x, y);
+                }
+            }
+            self.pos = 0;
+            self.end = x.try_encode_utf8(&mut self.buf).map_or(0, |bs| bs.len()) as u8;
+        }
+        self.pos += 1;
+        Some(Ok(self.buf[self.pos as usize - 1]))
+    }
+}
+
+impl<I: iter::FusedIterator<Item = u32>> iter::FusedIterator for EncodeWtf8<I> {}
+
+#[test]
+fn test_decode_wtf8() {
+    use std::vec::Vec;
+    use std::iter::FromIterator;
+    use {incomplete, invalid, ErrorKind::*};
+
+    assert_eq!(&[Ok(0x41), Ok(0xD800), Ok(0xDFFF), Ok(0x1F600), invalid(Overlong, 1),
+                 invalid(UnexpectedContinuation, 1),
+                 incomplete(3, 2)],
+               &Vec::from_iter(decode_wtf8(b"A\xED\xA0\x80\xED\xBF\xBF\xF0\x9F\x98\x80\xC0\x80\xED\xA0"
+                                               .iter().cloned()))[..]);
+}
+
+#[test]
+fn test_encode_wtf8() {
+    use std::vec::Vec;
+    use std::iter::FromIterator;
+    use {invalid, ErrorKind::*};
+
+    for &(xs, bs) in [(&[0x41u32, 0xD800, 0x41] as &[u32], b"A\xED\xA0\x80A" as &[u8]),
+                      (&[0xDFFF, 0xD800], b"\xED\xBF\xBF\xED\xA0\x80"),
+                      (&[0xD83D, 0xDE00], b"\xF0\x9F\x98\x80"),
+                      (&[0xD83D, 0xD83D, 0xDE00], b"\xED\xA0\xBD\xF0\x9F\x98\x80"),
+                      (&[0x1F600], b"\xF0\x9F\x98\x80")].iter() {
+        let encoded = Vec::from_iter(encode_wtf8(xs.iter().cloned()).map(Result::unwrap));
+        assert_eq!(bs, &encoded[..], "code points = {:04X?}", xs);
+        let ys = Vec::from_iter(decode_wtf8(bs.iter().cloned()).map(Result::unwrap));
+        assert_eq!(bs, &Vec::from_iter(encode_wtf8(ys).map(Result::unwrap))[..]);
+    }
+    assert_eq!(Some(invalid(OutOfRange, 1)), encode_wtf8(Some(0x110000)).next());
+}